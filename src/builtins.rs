@@ -0,0 +1,813 @@
+use crate::dirs;
+use crate::expand::is_name;
+use crate::history;
+use crate::lexer::quote;
+use crate::shell::Shell;
+use crate::status::Status;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read as _, Write};
+use std::iter::Peekable;
+use std::path::{Component, Path, PathBuf};
+use std::str::Chars;
+use std::time::Duration;
+
+/// A command that runs inside the shell rather than as a separate program,
+/// either because it needs to change the shell's state, like `cd`, or because
+/// it's simple enough to not be worth starting a process for, like `echo`.
+pub trait Builtin: Sync {
+    /// A one-line summary of what the builtin does, for `help`.
+    fn description(&self) -> &'static str;
+
+    /// Whether the builtin handles being called with `args`, rather than the
+    /// program of the same name.
+    fn accepts(&self, _args: &[String]) -> bool {
+        true
+    }
+
+    /// `args` does not include the builtin's name.
+    fn run(&self, shell: &mut Shell, args: &[String], streams: &mut Streams) -> Status;
+}
+
+// kept in alphabetical order, which is the order `help` lists them in
+static BUILTINS: &[(&str, &dyn Builtin)] = &[
+    ("cd", &Cd),
+    ("dirs", &dirs::Dirs),
+    ("echo", &Echo),
+    ("env", &Env),
+    ("exit", &Exit),
+    ("export", &Export),
+    ("hash", &Hash),
+    ("help", &Help),
+    ("history", &History),
+    ("popd", &dirs::Popd),
+    ("pushd", &dirs::Pushd),
+    ("pwd", &Pwd),
+    ("read", &Read),
+    ("set", &Set),
+    ("type", &Type),
+    ("unset", &Unset),
+    ("which", &Which),
+];
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    BUILTINS.iter().map(|&(name, _)| name)
+}
+
+/// Returns `None` if `command` is not a builtin, or is one that leaves these
+/// arguments to a program.
+pub fn find(command: &str, args: &[String]) -> Option<&'static dyn Builtin> {
+    BUILTINS
+        .iter()
+        .find(|&&(name, _)| name == command)
+        .map(|&(_, builtin)| builtin)
+        .filter(|builtin| builtin.accepts(args))
+}
+
+/// The input and output of a builtin.
+pub struct Streams {
+    pub stdin: File,
+    pub stdout: Output,
+    pub stderr: Output,
+}
+
+impl Streams {
+    /// Writes `output` to stdout, reporting a failure as an error from `name`.
+    pub fn print(&mut self, name: &str, output: impl AsRef<[u8]>) -> Status {
+        let result = self
+            .stdout
+            .write_all(output.as_ref())
+            // need to explicitly flush this to ensure it prints before the next command
+            .and_then(|()| self.stdout.flush());
+
+        match result {
+            Ok(()) => Status::SUCCESS,
+            Err(e) => {
+                let _ = writeln!(self.stderr, "{}: {}", name, e);
+                Status::FAILURE
+            }
+        }
+    }
+}
+
+/// Where a builtin's stdout or stderr goes. Builtins run inside the shell, so
+/// writing straight into a pipe could block forever when the command reading
+/// from it hasn't been started yet. Instead, anything not going to the shell's
+/// own stdout or stderr is collected, and written once the builtin is done.
+pub enum Output {
+    Stdout(io::Stdout),
+    Stderr(io::Stderr),
+    Buffered(Vec<u8>),
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(buf),
+            Output::Stderr(stderr) => stderr.write(buf),
+            Output::Buffered(bytes) => bytes.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::Stderr(stderr) => stderr.flush(),
+            Output::Buffered(_) => Ok(()),
+        }
+    }
+}
+
+struct Cd;
+
+impl Builtin for Cd {
+    fn description(&self) -> &'static str {
+        "change the current directory"
+    }
+
+    fn run(&self, _: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        // whether to print where we ended up, since it isn't what was typed
+        let mut should_print = false;
+
+        let target = match args {
+            [] => match env::var_os("HOME") {
+                Some(home) => PathBuf::from(home),
+                None => {
+                    let _ = writeln!(streams.stderr, "cd: HOME is not set");
+                    return Status::FAILURE;
+                }
+            },
+            [dir] if dir == "-" => match env::var_os("OLDPWD") {
+                Some(old) => {
+                    should_print = true;
+                    PathBuf::from(old)
+                }
+                None => {
+                    let _ = writeln!(streams.stderr, "cd: OLDPWD is not set");
+                    return Status::FAILURE;
+                }
+            },
+            [dir] => match search_cdpath(dir) {
+                Some(found) => {
+                    should_print = true;
+                    found
+                }
+                None => PathBuf::from(dir),
+            },
+            _ => {
+                let _ = writeln!(streams.stderr, "cd: too many arguments");
+                return Status::FAILURE;
+            }
+        };
+
+        if let Err(e) = dirs::change_directory(&target) {
+            let _ = writeln!(streams.stderr, "cd: {}", e);
+            return Status::FAILURE;
+        }
+
+        if should_print {
+            let current = env::current_dir().unwrap_or(target);
+            return streams.print("cd", format!("{}\n", current.display()));
+        }
+
+        Status::SUCCESS
+    }
+}
+
+// Like other shells, paths starting with `/`, `.` or `..` are never searched for.
+fn search_cdpath(dir: &str) -> Option<PathBuf> {
+    let path = Path::new(dir);
+    if !matches!(path.components().next(), Some(Component::Normal(_))) {
+        return None;
+    }
+
+    let cdpath = env::var_os("CDPATH")?;
+    env::split_paths(&cdpath)
+        // an empty entry means the current directory, which `cd` tries anyway
+        .filter(|base| !base.as_os_str().is_empty())
+        .map(|base| base.join(path))
+        .find(|candidate| candidate.is_dir())
+}
+
+struct Echo;
+
+impl Builtin for Echo {
+    fn description(&self) -> &'static str {
+        "print the arguments"
+    }
+
+    fn run(&self, _: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        let mut newline = true;
+        let mut escapes = false;
+
+        // like other shells, options are only looked for before the first word, and
+        // anything that isn't made up entirely of known options is printed as is
+        let mut words = args;
+        while let Some((first, rest)) = words.split_first() {
+            let flags = match first.strip_prefix('-') {
+                Some(flags) if !flags.is_empty() && flags.chars().all(|c| "neE".contains(c)) => {
+                    flags
+                }
+                _ => break,
+            };
+
+            for flag in flags.chars() {
+                match flag {
+                    'n' => newline = false,
+                    'e' => escapes = true,
+                    _ => escapes = false,
+                }
+            }
+            words = rest;
+        }
+
+        let text = words.join(" ");
+        let mut output = if escapes {
+            let (output, stopped) = unescape(&text);
+            if stopped {
+                newline = false;
+            }
+            output
+        } else {
+            text.into_bytes()
+        };
+        if newline {
+            output.push(b'\n');
+        }
+
+        streams.print("echo", output)
+    }
+}
+
+// Handles the escapes `echo -e` understands. Also returns whether a `\c` was
+// found, which means nothing more should be printed, not even the newline.
+fn unescape(text: &str) -> (Vec<u8>, bool) {
+    let mut output = Vec::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            output.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+
+        let byte = match chars.next() {
+            Some('a') => 0x07,
+            Some('b') => 0x08,
+            Some('c') => return (output, true),
+            Some('e') | Some('E') => 0x1b,
+            Some('f') => 0x0c,
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('v') => 0x0b,
+            Some('\\') => b'\\',
+            // like other shells, the value wraps around past 0o377
+            Some('0') => digits(&mut chars, 8, 3).unwrap_or(0) as u8,
+            Some('x') => match digits(&mut chars, 16, 2) {
+                Some(value) => value as u8,
+                None => {
+                    output.extend_from_slice(b"\\x");
+                    continue;
+                }
+            },
+            Some(other) => {
+                output.push(b'\\');
+                let mut buffer = [0; 4];
+                output.extend_from_slice(other.encode_utf8(&mut buffer).as_bytes());
+                continue;
+            }
+            None => b'\\',
+        };
+        output.push(byte);
+    }
+
+    (output, false)
+}
+
+// Reads up to `max` digits in the given radix, returning `None` if there were none.
+fn digits(chars: &mut Peekable<Chars>, radix: u32, max: usize) -> Option<u32> {
+    let mut value = None;
+    for _ in 0..max {
+        match chars.peek().and_then(|c| c.to_digit(radix)) {
+            Some(digit) => {
+                chars.next();
+                value = Some(value.unwrap_or(0) * radix + digit);
+            }
+            None => break,
+        }
+    }
+    value
+}
+
+struct Env;
+
+impl Builtin for Env {
+    fn description(&self) -> &'static str {
+        "list the environment variables"
+    }
+
+    // with arguments, `env` is the program that runs a command with a modified
+    // environment, which we leave to the real one
+    fn accepts(&self, args: &[String]) -> bool {
+        args.is_empty()
+    }
+
+    fn run(&self, _: &mut Shell, _: &[String], streams: &mut Streams) -> Status {
+        let mut vars: Vec<String> = env::vars_os()
+            .map(|(name, value)| {
+                format!("{}={}\n", name.to_string_lossy(), value.to_string_lossy())
+            })
+            .collect();
+        vars.sort();
+
+        streams.print("env", vars.concat())
+    }
+}
+
+struct Exit;
+
+impl Builtin for Exit {
+    fn description(&self) -> &'static str {
+        "end the session"
+    }
+
+    fn run(&self, shell: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        // default to the status of the previous command if one was not provided
+        match args.first().map(|code| code.parse()) {
+            None => {
+                shell.exiting = true;
+                shell.last_status
+            }
+            Some(Ok(code)) => {
+                shell.exiting = true;
+                Status::Exited(code)
+            }
+            Some(Err(_)) => {
+                let _ = writeln!(streams.stderr, "exit: expected a numeric exit code");
+                Status::Exited(2)
+            }
+        }
+    }
+}
+
+struct Export;
+
+impl Builtin for Export {
+    fn description(&self) -> &'static str {
+        "set environment variables, or list them"
+    }
+
+    fn run(&self, _: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        if args.is_empty() {
+            let mut vars: Vec<String> = env::vars_os()
+                .map(|(name, value)| {
+                    format!(
+                        "export {}={}\n",
+                        name.to_string_lossy(),
+                        quote(&value.to_string_lossy())
+                    )
+                })
+                .collect();
+            vars.sort();
+
+            return streams.print("export", vars.concat());
+        }
+
+        let mut status = Status::SUCCESS;
+        for arg in args {
+            // every variable is already in the environment, so `export NAME` only
+            // needs to check the name
+            let (name, value) = match arg.find('=') {
+                Some(i) => (&arg[..i], Some(&arg[i + 1..])),
+                None => (arg.as_str(), None),
+            };
+
+            if !is_name(name) {
+                let _ = writeln!(
+                    streams.stderr,
+                    "export: `{}` is not a valid variable name",
+                    name
+                );
+                status = Status::FAILURE;
+                continue;
+            }
+
+            if let Some(value) = value {
+                env::set_var(name, value);
+            }
+        }
+
+        status
+    }
+}
+
+// Reads a line of input into variables, splitting it on whitespace, with the
+// last variable getting whatever is left over. With no names, the whole line
+// goes in `REPLY`.
+struct Read;
+
+impl Builtin for Read {
+    fn description(&self) -> &'static str {
+        "read a line of input into variables"
+    }
+
+    fn run(&self, _: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        if let Some(name) = args.iter().find(|name| !is_name(name)) {
+            let _ = writeln!(
+                streams.stderr,
+                "read: `{}` is not a valid variable name",
+                name
+            );
+            return Status::Exited(2);
+        }
+
+        // one byte at a time, so nothing after the line is taken from whatever
+        // reads the same input next
+        let mut line = Vec::new();
+        let mut found_newline = false;
+        let mut byte = [0];
+        loop {
+            match streams.stdin.read(&mut byte) {
+                Ok(0) => break,
+                Ok(_) if byte[0] == b'\n' => {
+                    found_newline = true;
+                    break;
+                }
+                Ok(_) => line.push(byte[0]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    let _ = writeln!(streams.stderr, "read: {}", e);
+                    return Status::FAILURE;
+                }
+            }
+        }
+        let line = String::from_utf8_lossy(&line);
+
+        if args.is_empty() {
+            env::set_var("REPLY", line.as_ref());
+        } else {
+            let mut rest = line.trim();
+            for (i, name) in args.iter().enumerate() {
+                let value = if i + 1 == args.len() {
+                    rest
+                } else {
+                    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    let value = &rest[..end];
+                    rest = rest[end..].trim_start();
+                    value
+                };
+                env::set_var(name, value);
+            }
+        }
+
+        // like other shells, reaching the end of the input is a failure, so
+        // `read` can be used to loop over lines
+        if found_newline {
+            Status::SUCCESS
+        } else {
+            Status::FAILURE
+        }
+    }
+}
+
+struct Set;
+
+impl Builtin for Set {
+    fn description(&self) -> &'static str {
+        "change or list the shell's options"
+    }
+
+    fn run(&self, shell: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        fn on_or_off(option: bool) -> &'static str {
+            if option {
+                "on"
+            } else {
+                "off"
+            }
+        }
+
+        let (flag, option) = match args {
+            [] => {
+                let options = format!(
+                    "errexit\t{}\nfuzzysearch\t{}\npipefail\t{}\n",
+                    on_or_off(shell.errexit),
+                    on_or_off(shell.fuzzy_search),
+                    on_or_off(shell.pipefail)
+                );
+
+                return streams.print("set", options);
+            }
+            [flag, option] if flag == "-o" || flag == "+o" => (flag, option),
+            _ => {
+                let _ = writeln!(
+                    streams.stderr,
+                    "set: usage: set [-o|+o errexit|fuzzysearch|pipefail]"
+                );
+                return Status::Exited(2);
+            }
+        };
+
+        let value = flag == "-o";
+        match option.as_str() {
+            "errexit" => shell.errexit = value,
+            "fuzzysearch" => shell.fuzzy_search = value,
+            "pipefail" => shell.pipefail = value,
+            _ => {
+                let _ = writeln!(streams.stderr, "set: unknown option `{}`", option);
+                return Status::Exited(2);
+            }
+        }
+
+        Status::SUCCESS
+    }
+}
+
+struct Unset;
+
+impl Builtin for Unset {
+    fn description(&self) -> &'static str {
+        "remove environment variables"
+    }
+
+    fn run(&self, _: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        let mut status = Status::SUCCESS;
+        for name in args {
+            if is_name(name) {
+                env::remove_var(name);
+            } else {
+                let _ = writeln!(
+                    streams.stderr,
+                    "unset: `{}` is not a valid variable name",
+                    name
+                );
+                status = Status::FAILURE;
+            }
+        }
+        status
+    }
+}
+
+struct Hash;
+
+impl Builtin for Hash {
+    fn description(&self) -> &'static str {
+        "check that programs can be found, or with -r, find them all again"
+    }
+
+    fn run(&self, shell: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        match args {
+            [] => {
+                let _ = writeln!(streams.stderr, "hash: usage: hash [-r] [NAME...]");
+                Status::Exited(2)
+            }
+            // the list of programs is normally kept up to date by itself, but
+            // it won't notice, for example, a file becoming executable
+            [flag] if flag == "-r" => {
+                shell.path_index.rebuild();
+                Status::SUCCESS
+            }
+            names => {
+                let mut status = Status::SUCCESS;
+                for name in names {
+                    if shell.path_index.find_all(name).is_empty() {
+                        let _ = writeln!(streams.stderr, "hash: {}: not found", name);
+                        status = Status::FAILURE;
+                    }
+                }
+                status
+            }
+        }
+    }
+}
+
+struct Help;
+
+impl Builtin for Help {
+    fn description(&self) -> &'static str {
+        "list the builtins, or describe the named ones"
+    }
+
+    fn run(&self, _: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        let mut status = Status::SUCCESS;
+
+        let builtins: Vec<(&str, &dyn Builtin)> = if args.is_empty() {
+            BUILTINS.to_vec()
+        } else {
+            let mut builtins = Vec::with_capacity(args.len());
+            for arg in args {
+                match BUILTINS.iter().find(|&&(name, _)| name == arg) {
+                    Some(&builtin) => builtins.push(builtin),
+                    None => {
+                        let _ = writeln!(streams.stderr, "help: `{}` is not a builtin", arg);
+                        status = Status::FAILURE;
+                    }
+                }
+            }
+            builtins
+        };
+
+        let width = builtins
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        let output: String = builtins
+            .iter()
+            .map(|(name, builtin)| {
+                format!(
+                    "{:width$}  {}\n",
+                    name,
+                    builtin.description(),
+                    width = width
+                )
+            })
+            .collect();
+
+        if streams.print("help", output).success() {
+            status
+        } else {
+            Status::FAILURE
+        }
+    }
+}
+
+struct History;
+
+impl Builtin for History {
+    fn description(&self) -> &'static str {
+        "list the commands entered so far, with -l when and where they ran and how it went"
+    }
+
+    fn run(&self, shell: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        let (long, args) = match args.split_first() {
+            Some((flag, rest)) if flag == "-l" => (true, rest),
+            _ => (false, args),
+        };
+
+        // `history N` only lists the last N commands
+        let count = match args {
+            [] => shell.history.len(),
+            [count] => match count.parse::<usize>() {
+                Ok(count) => count.min(shell.history.len()),
+                Err(_) => {
+                    let _ = writeln!(streams.stderr, "history: expected a number of commands");
+                    return Status::Exited(2);
+                }
+            },
+            _ => {
+                let _ = writeln!(streams.stderr, "history: usage: history [-l] [N]");
+                return Status::Exited(2);
+            }
+        };
+
+        let start = shell.history.len() - count;
+        let output: String = shell.history[start..]
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                // numbered from 1, like other shells
+                let number = start + i + 1;
+                if !long {
+                    return format!("{:5}  {}\n", number, entry.command);
+                }
+
+                // entries from before these were recorded, and the command
+                // that is still running, have some of them missing
+                let unknown = || "-".to_string();
+                format!(
+                    "{:5}  {:19}  {:>8}  {:>3}  {}  {}\n",
+                    number,
+                    entry.started.map_or_else(unknown, history::format_time),
+                    entry.duration.map_or_else(unknown, format_duration),
+                    entry
+                        .status
+                        .map_or_else(unknown, |status| status.code().to_string()),
+                    entry
+                        .directory
+                        .as_deref()
+                        .map_or_else(unknown, dirs::abbreviate),
+                    entry.command
+                )
+            })
+            .collect();
+
+        streams.print("history", output)
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds < 60 {
+        format!("{:.2}s", duration.as_secs_f64())
+    } else if seconds < 60 * 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}h{:02}m", seconds / (60 * 60), seconds / 60 % 60)
+    }
+}
+
+struct Pwd;
+
+impl Builtin for Pwd {
+    fn description(&self) -> &'static str {
+        "print the current directory"
+    }
+
+    fn run(&self, _: &mut Shell, _: &[String], streams: &mut Streams) -> Status {
+        match env::current_dir() {
+            Ok(current) => streams.print("pwd", format!("{}\n", current.display())),
+            Err(e) => {
+                let _ = writeln!(streams.stderr, "pwd: {}", e);
+                Status::FAILURE
+            }
+        }
+    }
+}
+
+struct Type;
+
+impl Builtin for Type {
+    fn description(&self) -> &'static str {
+        "tell whether commands are builtins or programs, and where"
+    }
+
+    fn run(&self, shell: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        describe_commands("type", shell, args, streams, |name, found| match found {
+            Found::Builtin => format!("{} is a builtin\n", name),
+            Found::File(path) => format!("{} is {}\n", name, path.display()),
+        })
+    }
+}
+
+struct Which;
+
+impl Builtin for Which {
+    fn description(&self) -> &'static str {
+        "print the path of the program commands would run"
+    }
+
+    fn run(&self, shell: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        describe_commands("which", shell, args, streams, |name, found| match found {
+            Found::Builtin => format!("{}: builtin\n", name),
+            Found::File(path) => format!("{}\n", path.display()),
+        })
+    }
+}
+
+// what a command name could refer to
+enum Found {
+    Builtin,
+    File(PathBuf),
+}
+
+// Shared by `type` and `which`, which only differ in how they show what they
+// found. With `-a`, everything a name could refer to is shown, not just the one
+// that would actually run.
+fn describe_commands(
+    builtin_name: &str,
+    shell: &mut Shell,
+    args: &[String],
+    streams: &mut Streams,
+    show: impl Fn(&str, &Found) -> String,
+) -> Status {
+    let (all, names) = match args.split_first() {
+        Some((flag, names)) if flag == "-a" => (true, names),
+        _ => (false, args),
+    };
+    if names.is_empty() {
+        let _ = writeln!(
+            streams.stderr,
+            "{}: usage: {} [-a] NAME...",
+            builtin_name, builtin_name
+        );
+        return Status::Exited(2);
+    }
+
+    let mut status = Status::SUCCESS;
+    for name in names {
+        // builtins are checked first, since they are what would run
+        let mut found = Vec::new();
+        if BUILTINS.iter().any(|&(builtin, _)| builtin == name) {
+            found.push(Found::Builtin);
+        }
+        if all || found.is_empty() {
+            found.extend(shell.path_index.find_all(name).into_iter().map(Found::File));
+        }
+        if !all {
+            found.truncate(1);
+        }
+
+        if found.is_empty() {
+            let _ = writeln!(streams.stderr, "{}: {}: not found", builtin_name, name);
+            status = Status::FAILURE;
+        }
+        let output: String = found.iter().map(|found| show(name, found)).collect();
+        if !streams.print(builtin_name, output).success() {
+            return Status::FAILURE;
+        }
+    }
+
+    status
+}
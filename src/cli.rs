@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: er [OPTIONS] [SCRIPT | -c COMMAND]
+
+Runs executables. With no SCRIPT or COMMAND, starts an interactive session.
+
+Options:
+  -c COMMAND             Run COMMAND, then exit
+  -e, --errexit          Stop at the first command that fails
+      --history-file PATH
+                         Read and save history at PATH
+      --no-history       Do not read or save history
+  -q, --quiet            Do not print the banner at startup
+  -h, --help             Print this message, then exit
+  -V, --version          Print the version, then exit";
+
+pub enum Action {
+    Run(Options),
+    PrintHelp,
+    PrintVersion,
+}
+
+pub enum Mode {
+    Interactive,
+    Command(String),
+    Script(PathBuf),
+}
+
+pub struct Options {
+    pub mode: Mode,
+    pub errexit: bool,
+    // `None` means use the default location
+    pub history_file: Option<PathBuf>,
+    pub no_history: bool,
+    pub quiet: bool,
+}
+
+pub fn parse<I>(args: I) -> Result<Action, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = Options {
+        mode: Mode::Interactive,
+        errexit: false,
+        history_file: None,
+        no_history: false,
+        quiet: false,
+    };
+
+    let mut args = args.into_iter();
+    let mut only_positional = false;
+    while let Some(arg) = args.next() {
+        if only_positional || !arg.starts_with('-') {
+            if let Mode::Interactive = options.mode {
+                options.mode = Mode::Script(PathBuf::from(arg));
+                continue;
+            }
+            return Err(format!("unexpected argument `{}`", arg));
+        }
+
+        // allow `--flag=value` as well as `--flag value`
+        let (flag, mut value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (&arg[..i], Some(arg[i + 1..].to_string())),
+            _ => (arg.as_str(), None),
+        };
+
+        let mut takes_value = |name: &str| {
+            value
+                .take()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("`{}` requires {}", flag, name))
+        };
+
+        match flag {
+            "--" => only_positional = true,
+            "-c" => {
+                let command = takes_value("a command")?;
+                if let Mode::Interactive = options.mode {
+                    options.mode = Mode::Command(command);
+                } else {
+                    return Err("only one of a script or `-c` can be given".to_string());
+                }
+            }
+            "-e" | "--errexit" => options.errexit = true,
+            "--history-file" => options.history_file = Some(PathBuf::from(takes_value("a path")?)),
+            "--no-history" => options.no_history = true,
+            "-q" | "--quiet" => options.quiet = true,
+            "-h" | "--help" => return Ok(Action::PrintHelp),
+            "-V" | "--version" => return Ok(Action::PrintVersion),
+            _ => return Err(format!("unknown option `{}`", flag)),
+        }
+
+        if value.is_some() {
+            return Err(format!("`{}` does not take a value", flag));
+        }
+    }
+
+    Ok(Action::Run(options))
+}
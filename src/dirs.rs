@@ -0,0 +1,202 @@
+use crate::builtins::{Builtin, Streams};
+use crate::shell::Shell;
+use crate::status::Status;
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Changes the current directory, and keeps `PWD` and `OLDPWD` up to date for
+/// the commands we run.
+pub fn change_directory(target: &Path) -> Result<(), String> {
+    let previous = env::current_dir().ok();
+
+    env::set_current_dir(target).map_err(|e| format!("{}: {}", target.display(), e))?;
+
+    if let Some(previous) = previous {
+        env::set_var("OLDPWD", previous);
+    }
+    if let Ok(current) = env::current_dir() {
+        env::set_var("PWD", current);
+    }
+
+    Ok(())
+}
+
+// Like other shells, entry 0 of the stack is always the current directory, and
+// `shell.dir_stack` holds the rest.
+fn full_stack(shell: &Shell) -> Result<Vec<PathBuf>, String> {
+    let current = env::current_dir().map_err(|e| e.to_string())?;
+
+    let mut stack = Vec::with_capacity(shell.dir_stack.len() + 1);
+    stack.push(current);
+    stack.extend(shell.dir_stack.iter().cloned());
+    Ok(stack)
+}
+
+// Parses `+N`, counting from the top of the stack, or `-N`, counting from the bottom.
+fn parse_index(arg: &str, stack_len: usize) -> Option<Result<usize, String>> {
+    let (from_top, digits) = if let Some(digits) = arg.strip_prefix('+') {
+        (true, digits)
+    } else if let Some(digits) = arg.strip_prefix('-') {
+        (false, digits)
+    } else {
+        return None;
+    };
+
+    let n: usize = match digits.parse() {
+        Ok(n) => n,
+        Err(_) => return Some(Err(format!("`{}` is not a valid stack index", arg))),
+    };
+    if n >= stack_len {
+        return Some(Err(format!("{}: directory stack index out of range", arg)));
+    }
+
+    Some(Ok(if from_top { n } else { stack_len - 1 - n }))
+}
+
+pub struct Pushd;
+
+impl Builtin for Pushd {
+    fn description(&self) -> &'static str {
+        "add a directory to the directory stack"
+    }
+
+    fn run(&self, shell: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        let result = full_stack(shell).and_then(|mut stack| {
+            match args {
+                // swap the top two directories
+                [] => {
+                    if stack.len() < 2 {
+                        return Err("no other directory".to_string());
+                    }
+                    stack.swap(0, 1);
+                }
+                [arg] => match parse_index(arg, stack.len()) {
+                    Some(index) => stack.rotate_left(index?),
+                    None => stack.insert(0, PathBuf::from(arg)),
+                },
+                _ => return Err("too many arguments".to_string()),
+            }
+
+            change_directory(&stack[0])?;
+            stack.remove(0);
+            shell.dir_stack = stack;
+            Ok(())
+        });
+
+        finish("pushd", shell, result, streams)
+    }
+}
+
+pub struct Popd;
+
+impl Builtin for Popd {
+    fn description(&self) -> &'static str {
+        "remove a directory from the directory stack"
+    }
+
+    fn run(&self, shell: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        let result = full_stack(shell).and_then(|mut stack| {
+            if stack.len() < 2 {
+                return Err("directory stack empty".to_string());
+            }
+
+            let index = match args {
+                [] => 0,
+                [arg] => match parse_index(arg, stack.len()) {
+                    Some(index) => index?,
+                    None => return Err(format!("`{}` is not a valid stack index", arg)),
+                },
+                _ => return Err("too many arguments".to_string()),
+            };
+
+            stack.remove(index);
+            if index == 0 {
+                change_directory(&stack[0])?;
+            }
+            stack.remove(0);
+            shell.dir_stack = stack;
+            Ok(())
+        });
+
+        finish("popd", shell, result, streams)
+    }
+}
+
+pub struct Dirs;
+
+impl Builtin for Dirs {
+    fn description(&self) -> &'static str {
+        "list the directory stack"
+    }
+
+    fn run(&self, shell: &mut Shell, args: &[String], streams: &mut Streams) -> Status {
+        let mut verbose = false;
+        for arg in args {
+            match arg.as_str() {
+                "-c" => shell.dir_stack.clear(),
+                "-v" => verbose = true,
+                _ => {
+                    let _ = writeln!(streams.stderr, "dirs: usage: dirs [-c] [-v]");
+                    return Status::Exited(2);
+                }
+            }
+        }
+
+        let stack = match full_stack(shell) {
+            Ok(stack) => stack,
+            Err(e) => {
+                let _ = writeln!(streams.stderr, "dirs: {}", e);
+                return Status::FAILURE;
+            }
+        };
+
+        let output = if verbose {
+            stack
+                .iter()
+                .enumerate()
+                .map(|(i, dir)| format!("{:2}  {}\n", i, abbreviate(dir)))
+                .collect()
+        } else {
+            let mut line = stack
+                .iter()
+                .map(|dir| abbreviate(dir))
+                .collect::<Vec<_>>()
+                .join(" ");
+            line.push('\n');
+            line
+        };
+
+        streams.print("dirs", output)
+    }
+}
+
+// like other shells, a successful `pushd` or `popd` shows the new stack
+fn finish(
+    name: &str,
+    shell: &mut Shell,
+    result: Result<(), String>,
+    streams: &mut Streams,
+) -> Status {
+    match result {
+        Ok(()) => Dirs.run(shell, &[], streams),
+        Err(e) => {
+            let _ = writeln!(streams.stderr, "{}: {}", name, e);
+            Status::FAILURE
+        }
+    }
+}
+
+// Shows the home directory as `~`, which also makes the paths easier to type as
+// arguments to `pushd` and `popd`.
+pub fn abbreviate(dir: &Path) -> String {
+    if let Some(home) = env::var_os("HOME").filter(|home| !home.is_empty()) {
+        match dir.strip_prefix(&home) {
+            Ok(rest) if rest.as_os_str().is_empty() => return "~".to_string(),
+            Ok(rest) => return Path::new("~").join(rest).display().to_string(),
+            Err(_) => {}
+        }
+    }
+
+    dir.display().to_string()
+}
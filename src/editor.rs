@@ -0,0 +1,940 @@
+use crate::history::Entry;
+use std::fmt::Write as _;
+use std::io::{self, stdin, stdout, IsTerminal, Write};
+use std::mem;
+
+/// Shows `prompt` and reads a line of input, which like with
+/// `BufRead::read_line` includes its newline. Returns `None` at the end of the
+/// input.
+///
+/// When both stdin and stdout are terminals, the line can be edited before it
+/// is entered, and Up and Down step through the commands in `history`, oldest
+/// first, that start with whatever was typed before Up was first pressed.
+/// Ctrl-R searches `history` for lines containing what is typed next, or with
+/// `fuzzy`, containing its characters in order with anything in between.
+/// Otherwise, (when input is piped in for example,) lines are read as is.
+pub fn read_line(prompt: &str, history: &[Entry], fuzzy: bool) -> io::Result<Option<String>> {
+    if !stdin().is_terminal() || !stdout().is_terminal() {
+        print!("{}", prompt);
+        // need to explicitly flush this to ensure it prints before we read
+        stdout().flush()?;
+
+        // One byte at a time rather than through `stdin()`'s buffer, so
+        // nothing after the line is taken from the commands that read the
+        // same input next, like `read`.
+        let mut input = Vec::new();
+        while let Some(byte) = read_byte()? {
+            input.push(byte);
+            if byte == b'\n' {
+                break;
+            }
+        }
+        if input.is_empty() {
+            return Ok(None);
+        }
+
+        return String::from_utf8(input)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+
+    let _raw_mode = RawMode::enable()?;
+    let mut line = Line {
+        prompt: prompt.to_string(),
+        chars: Vec::new(),
+        cursor: 0,
+        highlighted: Vec::new(),
+        cursor_row: 0,
+    };
+    line.render()?;
+
+    // while stepping through the history, the entry we are on and the line
+    // that was there before we started
+    let mut navigation: Option<(usize, Vec<char>)> = None;
+    // a key that ended a search, which still needs to do what it normally does
+    let mut pending = None;
+
+    loop {
+        let key = match pending.take() {
+            Some(key) => key,
+            None => read_key()?,
+        };
+        if !matches!(key, Key::Up | Key::Down) {
+            navigation = None;
+        }
+
+        match key {
+            Key::Char(c) => {
+                line.chars.insert(line.cursor, c);
+                line.cursor += 1;
+            }
+            Key::Enter => {
+                line.finish("\r\n")?;
+                let mut input: String = line.chars.into_iter().collect();
+                input.push('\n');
+                return Ok(Some(input));
+            }
+            Key::Interrupt => {
+                // like other shells, the line is abandoned rather than ending the session
+                line.finish("^C\r\n")?;
+                return Ok(Some(String::new()));
+            }
+            // like other shells, Ctrl-D only ends the input when there is no line
+            Key::DeleteOrEnd if line.chars.is_empty() => return Ok(None),
+            Key::Delete | Key::DeleteOrEnd => {
+                let end = line.next_boundary(line.cursor);
+                line.chars.drain(line.cursor..end);
+            }
+            Key::Backspace => {
+                let start = line.previous_boundary(line.cursor);
+                line.chars.drain(start..line.cursor);
+                line.cursor = start;
+            }
+            Key::Left => line.cursor = line.previous_boundary(line.cursor),
+            Key::Right => line.cursor = line.next_boundary(line.cursor),
+            Key::Home => line.cursor = 0,
+            Key::End => line.cursor = line.chars.len(),
+            Key::WordLeft => line.cursor = line.word_start(),
+            Key::WordRight => line.cursor = line.word_end(),
+            Key::KillToEnd => line.chars.truncate(line.cursor),
+            Key::KillToStart => {
+                line.chars.drain(..line.cursor);
+                line.cursor = 0;
+            }
+            Key::KillWord => {
+                // unlike moving by words, this goes back to the previous whitespace
+                let mut start = line.cursor;
+                while start > 0 && line.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                while start > 0 && !line.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                line.chars.drain(start..line.cursor);
+                line.cursor = start;
+            }
+            Key::ClearScreen => {
+                print!("\x1b[H\x1b[2J");
+                line.cursor_row = 0;
+            }
+            Key::Up => {
+                let (end, typed) = match navigation {
+                    Some((index, ref typed)) => (index, typed.clone()),
+                    None => (history.len(), line.chars.clone()),
+                };
+
+                match find_entry(history, (0..end).rev(), &typed, &line.chars) {
+                    Some((index, entry)) => {
+                        line.chars = entry;
+                        line.cursor = line.chars.len();
+                        navigation = Some((index, typed));
+                    }
+                    // already on the oldest match
+                    None => continue,
+                }
+            }
+            Key::Down => {
+                let (start, typed) = match navigation.take() {
+                    Some((index, typed)) => (index + 1, typed),
+                    None => continue,
+                };
+
+                match find_entry(history, start..history.len(), &typed, &line.chars) {
+                    Some((index, entry)) => {
+                        line.chars = entry;
+                        navigation = Some((index, typed));
+                    }
+                    // past the newest entry is the line as it was
+                    None => line.chars = typed,
+                }
+                line.cursor = line.chars.len();
+            }
+            Key::Search => pending = search(&mut line, history, fuzzy)?,
+            Key::Cancel | Key::Unknown => continue,
+        }
+
+        line.render()?;
+    }
+}
+
+// Bash style incremental search, which runs until a key other than those for
+// changing what to search for is pressed. Cancelling puts the line back how it
+// was, and any other key leaves the entry that was found in its place. That key
+// is returned so it can then do what it normally does, so for example, Enter
+// runs the entry.
+fn search(line: &mut Line, history: &[Entry], fuzzy: bool) -> io::Result<Option<Key>> {
+    let prompt = mem::take(&mut line.prompt);
+    let original = (line.chars.clone(), line.cursor);
+
+    let mut query: Vec<char> = Vec::new();
+    // the index of the entry being shown, if any
+    let mut found: Option<usize> = None;
+    let mut failed = false;
+
+    let key = loop {
+        line.prompt = format!(
+            "({}reverse-i-search)`{}': ",
+            if failed { "failed " } else { "" },
+            query.iter().collect::<String>()
+        );
+        line.render()?;
+
+        // the entry to search back from, not including itself
+        let end = match read_key()? {
+            Key::Char(c) => {
+                query.push(c);
+                // the entry being shown may still match
+                found.map_or(history.len(), |index| index + 1)
+            }
+            Key::Backspace => {
+                query.pop();
+                history.len()
+            }
+            Key::Search => found.unwrap_or(history.len()),
+            Key::Cancel => {
+                line.chars = original.0;
+                line.cursor = original.1;
+                break None;
+            }
+            key => break Some(key),
+        };
+
+        if query.is_empty() {
+            line.chars = original.0.clone();
+            line.cursor = original.1;
+            line.highlighted.clear();
+            found = None;
+            failed = false;
+            continue;
+        }
+
+        let result = (0..end).rev().find_map(|index| {
+            let entry: Vec<char> = history[index].command.chars().collect();
+            // looking for older matches shouldn't show the same line again
+            if found.is_some_and(|found| found != index) && entry == line.chars {
+                return None;
+            }
+
+            match_positions(&entry, &query, fuzzy).map(|positions| (index, entry, positions))
+        });
+
+        match result {
+            Some((index, entry, positions)) => {
+                line.cursor = positions[0];
+                line.chars = entry;
+                line.highlighted = positions;
+                found = Some(index);
+                failed = false;
+            }
+            // like other shells, the last match stays while the search is failing
+            None => failed = true,
+        }
+    };
+
+    line.prompt = prompt;
+    line.highlighted.clear();
+    line.render()?;
+    Ok(key)
+}
+
+// Returns the indexes of the characters in `entry` that match `query`, or
+// `None` if it doesn't match. `query` must not be empty.
+fn match_positions(entry: &[char], query: &[char], fuzzy: bool) -> Option<Vec<usize>> {
+    if fuzzy {
+        let mut positions = Vec::with_capacity(query.len());
+        let mut rest = query.iter().peekable();
+        for (i, c) in entry.iter().enumerate() {
+            if rest.peek() == Some(&c) {
+                positions.push(i);
+                rest.next();
+            }
+        }
+
+        if rest.peek().is_none() {
+            Some(positions)
+        } else {
+            None
+        }
+    } else {
+        let start = entry
+            .windows(query.len())
+            .position(|window| window == query)?;
+        Some((start..start + query.len()).collect())
+    }
+}
+
+// Returns the first entry, checking them in the order of `indices`, that
+// starts with `typed`. Entries the same as the line already showing are
+// skipped, so repeating a command doesn't mean having to go past it repeatedly.
+fn find_entry(
+    history: &[Entry],
+    indices: impl Iterator<Item = usize>,
+    typed: &[char],
+    showing: &[char],
+) -> Option<(usize, Vec<char>)> {
+    for index in indices {
+        let entry: Vec<char> = history[index].command.chars().collect();
+
+        if entry.starts_with(typed) && entry != showing {
+            return Some((index, entry));
+        }
+    }
+
+    None
+}
+
+// Puts the terminal in raw mode, so we get each key as it is pressed and can
+// draw the line ourselves, until this is dropped. Being dropped while a panic
+// unwinds means the terminal is restored then too.
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        // this is the kind of struct that is meant to be zeroed and then filled in
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // like `cfmakeraw`, except output is left alone so `\n` still starts a new line
+        let mut raw = original;
+        raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+        raw.c_cflag |= libc::CS8;
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+
+        // `TCSADRAIN` rather than `TCSAFLUSH` so anything typed ahead is kept
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // there's nothing more we could do if this fails
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original);
+        }
+    }
+}
+
+struct Line {
+    prompt: String,
+    chars: Vec<char>,
+    // an index into `chars`
+    cursor: usize,
+    // indexes into `chars`, of the parts of a search result that matched
+    highlighted: Vec<usize>,
+    // which of the rows the line takes up the terminal's cursor is on, counting
+    // from the one the prompt starts on
+    cursor_row: usize,
+}
+
+impl Line {
+    // Moves the cursor to the end before leaving the line for good.
+    fn finish(&mut self, end: &str) -> io::Result<()> {
+        self.cursor = self.chars.len();
+        self.render()?;
+        print!("{}", end);
+        stdout().flush()
+    }
+
+    // Redraws the prompt and line, which can take up several rows when it is
+    // wider than the terminal.
+    fn render(&mut self) -> io::Result<()> {
+        let columns = terminal_columns();
+        let mut output = String::new();
+
+        // back to where the prompt starts, clearing everything after it
+        if self.cursor_row > 0 {
+            let _ = write!(output, "\x1b[{}A", self.cursor_row);
+        }
+        output.push_str("\r\x1b[J");
+        output.push_str(&self.prompt);
+        for (i, &c) in self.chars.iter().enumerate() {
+            if self.highlighted.contains(&i) {
+                // bold and underlined
+                output.push_str("\x1b[1;4m");
+                push_shown(&mut output, c);
+                output.push_str("\x1b[22;24m");
+            } else {
+                push_shown(&mut output, c);
+            }
+        }
+
+        // work out where the terminal put everything, to know where the cursor goes
+        let prompt_length = self.prompt.chars().count();
+        let mut cursor = None;
+        let (mut row, mut column) = (0, 0);
+        for (i, c) in self
+            .prompt
+            .chars()
+            .chain(self.chars.iter().copied())
+            .enumerate()
+        {
+            let width = char_width(c);
+            // a wide character that doesn't fit goes on the next row
+            if column + width > columns {
+                row += 1;
+                column = 0;
+            }
+            if i == prompt_length + self.cursor {
+                cursor = Some((row, column));
+            }
+            column += width;
+        }
+        // terminals wait for another character before moving to the next row
+        // after filling one, so we move there ourselves
+        if column >= columns {
+            output.push_str("\r\n");
+            row += 1;
+            column = 0;
+        }
+        let (cursor_row, cursor_column) = cursor.unwrap_or((row, column));
+
+        if row > cursor_row {
+            let _ = write!(output, "\x1b[{}A", row - cursor_row);
+        }
+        output.push('\r');
+        if cursor_column > 0 {
+            let _ = write!(output, "\x1b[{}C", cursor_column);
+        }
+        self.cursor_row = cursor_row;
+
+        let mut stdout = stdout();
+        stdout.write_all(output.as_bytes())?;
+        stdout.flush()
+    }
+
+    // Combining characters are kept with the character before them, so the
+    // cursor never ends up between the two.
+    fn previous_boundary(&self, mut index: usize) -> usize {
+        while index > 0 {
+            index -= 1;
+            if char_width(self.chars[index]) > 0 {
+                break;
+            }
+        }
+        index
+    }
+
+    fn next_boundary(&self, mut index: usize) -> usize {
+        if index < self.chars.len() {
+            index += 1;
+        }
+        while index < self.chars.len() && char_width(self.chars[index]) == 0 {
+            index += 1;
+        }
+        index
+    }
+
+    // For Alt-B.
+    fn word_start(&self) -> usize {
+        let mut index = self.cursor;
+        while index > 0 && !is_word_char(self.chars[index - 1]) {
+            index -= 1;
+        }
+        while index > 0 && is_word_char(self.chars[index - 1]) {
+            index -= 1;
+        }
+        index
+    }
+
+    // For Alt-F.
+    fn word_end(&self) -> usize {
+        let mut index = self.cursor;
+        while index < self.chars.len() && !is_word_char(self.chars[index]) {
+            index += 1;
+        }
+        while index < self.chars.len() && is_word_char(self.chars[index]) {
+            index += 1;
+        }
+        index
+    }
+}
+
+// Control characters can't be typed into the line, but they can come from
+// history, like the newlines in a command that spanned several lines. They are
+// shown as `^J` and so on, which `char_width` accounts for.
+fn push_shown(output: &mut String, c: char) {
+    if c.is_ascii_control() {
+        output.push('^');
+        output.push((c as u8 ^ 0x40) as char);
+    } else {
+        output.push(c);
+    }
+}
+
+// Words are runs of letters and numbers, along with any combining characters
+// on them.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || char_width(c) == 0
+}
+
+fn terminal_columns() -> usize {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+
+    if result == 0 && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        80
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Enter,
+    // Ctrl-C
+    Interrupt,
+    // Ctrl-D, which deletes unless the line is empty
+    DeleteOrEnd,
+    Delete,
+    Backspace,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    // Alt-B, or Ctrl or Alt with the left arrow
+    WordLeft,
+    // Alt-F, or Ctrl or Alt with the right arrow
+    WordRight,
+    // Ctrl-K
+    KillToEnd,
+    // Ctrl-U
+    KillToStart,
+    // Ctrl-W
+    KillWord,
+    // Ctrl-L
+    ClearScreen,
+    // Ctrl-R
+    Search,
+    // Ctrl-G, or Escape on its own
+    Cancel,
+    // including control characters, which would throw off where we think
+    // things are on the screen if we let them in
+    Unknown,
+}
+
+// how long to wait after an Escape for the rest of a sequence, in milliseconds
+const ESCAPE_TIMEOUT: i32 = 50;
+
+fn read_key() -> io::Result<Key> {
+    let key = match read_byte()? {
+        // the terminal is gone, which we treat like Ctrl-D
+        None => Key::DeleteOrEnd,
+        Some(0x01) => Key::Home,
+        Some(0x02) => Key::Left,
+        Some(0x03) => Key::Interrupt,
+        Some(0x04) => Key::DeleteOrEnd,
+        Some(0x05) => Key::End,
+        Some(0x06) => Key::Right,
+        Some(0x08) | Some(0x7f) => Key::Backspace,
+        Some(0x0b) => Key::KillToEnd,
+        Some(0x07) => Key::Cancel,
+        Some(0x0c) => Key::ClearScreen,
+        Some(b'\r') | Some(b'\n') => Key::Enter,
+        Some(0x0e) => Key::Down,
+        Some(0x10) => Key::Up,
+        Some(0x12) => Key::Search,
+        Some(0x15) => Key::KillToStart,
+        Some(0x17) => Key::KillWord,
+        Some(0x1b) => read_escape()?,
+        Some(byte) if byte < 0x20 => Key::Unknown,
+        Some(byte) => read_char(byte)?.map_or(Key::Unknown, Key::Char),
+    };
+
+    Ok(key)
+}
+
+// Expects the Escape to have been read already. A lone Escape can't be told
+// apart from the start of a sequence, except by nothing else arriving soon after.
+fn read_escape() -> io::Result<Key> {
+    if !input_within(ESCAPE_TIMEOUT)? {
+        return Ok(Key::Cancel);
+    }
+
+    let key = match read_byte()? {
+        Some(b'b') => Key::WordLeft,
+        Some(b'f') => Key::WordRight,
+        Some(b'[') => {
+            // parameters, then a final byte from `@` to `~`
+            let mut parameters = String::new();
+            loop {
+                match read_byte()? {
+                    Some(byte @ 0x40..=0x7e) => break escape_sequence(&parameters, byte),
+                    Some(byte) => parameters.push(byte as char),
+                    None => break Key::Unknown,
+                }
+            }
+        }
+        Some(b'O') => match read_byte()? {
+            Some(byte) => escape_sequence("", byte),
+            None => Key::Unknown,
+        },
+        _ => Key::Unknown,
+    };
+
+    Ok(key)
+}
+
+fn escape_sequence(parameters: &str, final_byte: u8) -> Key {
+    match (parameters, final_byte) {
+        ("", b'A') => Key::Up,
+        ("", b'B') => Key::Down,
+        ("", b'C') => Key::Right,
+        ("", b'D') => Key::Left,
+        ("", b'H') | ("1", b'~') | ("7", b'~') => Key::Home,
+        ("", b'F') | ("4", b'~') | ("8", b'~') => Key::End,
+        ("3", b'~') => Key::Delete,
+        ("1;3", b'C') | ("1;5", b'C') => Key::WordRight,
+        ("1;3", b'D') | ("1;5", b'D') => Key::WordLeft,
+        _ => Key::Unknown,
+    }
+}
+
+// `first` is the first byte of a character encoded as UTF-8.
+fn read_char(first: u8) -> io::Result<Option<char>> {
+    let length = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(None),
+    };
+
+    let mut bytes = vec![first];
+    for _ in 1..length {
+        match read_byte()? {
+            Some(byte) => bytes.push(byte),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|s| s.chars().next()))
+}
+
+// Reads straight from the file descriptor, one byte at a time, so nothing
+// typed after the line is taken from the commands that run next, and so
+// `input_within` knows if there is more to read.
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = 0;
+    loop {
+        let result = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+        match result {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+fn input_within(milliseconds: i32) -> io::Result<bool> {
+    let mut poll_fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    match unsafe { libc::poll(&mut poll_fd, 1, milliseconds) } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// How many columns a terminal uses to show `c`. This covers the common wide
+/// and zero width characters rather than all of Unicode's rules.
+fn char_width(c: char) -> usize {
+    // as `push_shown` shows them
+    if c.is_ascii_control() {
+        return 2;
+    }
+    let c = c as u32;
+
+    const ZERO_WIDTH: &[(u32, u32)] = &[
+        // combining marks
+        (0x0300, 0x036f),
+        (0x0483, 0x0489),
+        (0x0591, 0x05bd),
+        (0x0610, 0x061a),
+        (0x064b, 0x065f),
+        (0x0e31, 0x0e31),
+        (0x0e34, 0x0e3a),
+        (0x0e47, 0x0e4e),
+        (0x1ab0, 0x1aff),
+        (0x1dc0, 0x1dff),
+        // zero width spaces and joiners
+        (0x200b, 0x200f),
+        (0x20d0, 0x20ff),
+        // variation selectors
+        (0xfe00, 0xfe0f),
+        (0xfe20, 0xfe2f),
+        (0xe0100, 0xe01ef),
+    ];
+    const WIDE: &[(u32, u32)] = &[
+        // Hangul Jamo
+        (0x1100, 0x115f),
+        (0x231a, 0x231b),
+        (0x2329, 0x232a),
+        (0x23e9, 0x23ec),
+        (0x23f0, 0x23f0),
+        (0x23f3, 0x23f3),
+        (0x25fd, 0x25fe),
+        (0x2614, 0x2615),
+        (0x2648, 0x2653),
+        (0x267f, 0x267f),
+        (0x2693, 0x2693),
+        (0x26a1, 0x26a1),
+        (0x26aa, 0x26ab),
+        (0x26bd, 0x26be),
+        (0x26c4, 0x26c5),
+        (0x26ce, 0x26ce),
+        (0x26d4, 0x26d4),
+        (0x26ea, 0x26ea),
+        (0x26f2, 0x26f3),
+        (0x26f5, 0x26f5),
+        (0x26fa, 0x26fa),
+        (0x26fd, 0x26fd),
+        (0x2705, 0x2705),
+        (0x270a, 0x270b),
+        (0x2728, 0x2728),
+        (0x274c, 0x274c),
+        (0x274e, 0x274e),
+        (0x2753, 0x2755),
+        (0x2757, 0x2757),
+        (0x2795, 0x2797),
+        (0x27b0, 0x27b0),
+        (0x27bf, 0x27bf),
+        (0x2b1b, 0x2b1c),
+        (0x2b50, 0x2b50),
+        (0x2b55, 0x2b55),
+        // CJK, Hiragana, Katakana and friends
+        (0x2e80, 0x303e),
+        (0x3041, 0x33ff),
+        (0x3400, 0x4dbf),
+        (0x4e00, 0x9fff),
+        (0xa000, 0xa4cf),
+        (0xa960, 0xa97f),
+        // Hangul syllables
+        (0xac00, 0xd7a3),
+        (0xf900, 0xfaff),
+        (0xfe10, 0xfe19),
+        (0xfe30, 0xfe6f),
+        // fullwidth forms
+        (0xff00, 0xff60),
+        (0xffe0, 0xffe6),
+        (0x16fe0, 0x16fe4),
+        (0x17000, 0x18cff),
+        (0x1b000, 0x1b2ff),
+        // emoji
+        (0x1f004, 0x1f004),
+        (0x1f0cf, 0x1f0cf),
+        (0x1f18e, 0x1f18e),
+        (0x1f191, 0x1f19a),
+        (0x1f200, 0x1f202),
+        (0x1f210, 0x1f23b),
+        (0x1f240, 0x1f248),
+        (0x1f250, 0x1f251),
+        (0x1f260, 0x1f265),
+        (0x1f300, 0x1f320),
+        (0x1f32d, 0x1f335),
+        (0x1f337, 0x1f37c),
+        (0x1f37e, 0x1f393),
+        (0x1f3a0, 0x1f3ca),
+        (0x1f3cf, 0x1f3d3),
+        (0x1f3e0, 0x1f3f0),
+        (0x1f3f4, 0x1f3f4),
+        (0x1f3f8, 0x1f43e),
+        (0x1f440, 0x1f440),
+        (0x1f442, 0x1f4fc),
+        (0x1f4ff, 0x1f53d),
+        (0x1f54b, 0x1f54e),
+        (0x1f550, 0x1f567),
+        (0x1f57a, 0x1f57a),
+        (0x1f595, 0x1f596),
+        (0x1f5a4, 0x1f5a4),
+        (0x1f5fb, 0x1f64f),
+        (0x1f680, 0x1f6c5),
+        (0x1f6cc, 0x1f6cc),
+        (0x1f6d0, 0x1f6d2),
+        (0x1f6d5, 0x1f6d7),
+        (0x1f6eb, 0x1f6ec),
+        (0x1f6f4, 0x1f6fc),
+        (0x1f7e0, 0x1f7eb),
+        (0x1f90c, 0x1f93a),
+        (0x1f93c, 0x1f945),
+        (0x1f947, 0x1f9ff),
+        (0x1fa70, 0x1faff),
+        // the rest of CJK
+        (0x20000, 0x2fffd),
+        (0x30000, 0x3fffd),
+    ];
+
+    let is_in = |ranges: &[(u32, u32)]| ranges.iter().any(|&(low, high)| low <= c && c <= high);
+
+    if is_in(ZERO_WIDTH) {
+        0
+    } else if is_in(WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    fn line(text: &str, cursor: usize) -> Line {
+        Line {
+            prompt: String::new(),
+            chars: chars(text),
+            cursor,
+            highlighted: Vec::new(),
+            cursor_row: 0,
+        }
+    }
+
+    fn history(commands: &[&str]) -> Vec<Entry> {
+        commands
+            .iter()
+            .map(|command| Entry {
+                command: command.to_string(),
+                started: None,
+                directory: None,
+                duration: None,
+                status: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finding_entries() {
+        let history = history(&["ls", "cargo build", "cd src", "cargo test", "cargo test"]);
+        let up = |typed: &str, showing: &str| {
+            find_entry(
+                &history,
+                (0..history.len()).rev(),
+                &chars(typed),
+                &chars(showing),
+            )
+            .map(|(index, _)| index)
+        };
+
+        assert_eq!(up("", ""), Some(4));
+        assert_eq!(up("c", ""), Some(4));
+        assert_eq!(up("cd", ""), Some(2));
+        assert_eq!(up("x", ""), None);
+        // the repeated command is only stepped through once
+        assert_eq!(up("cargo", "cargo test"), Some(1));
+
+        let down = find_entry(&history, 2..history.len(), &chars("cargo"), &chars(""));
+        assert_eq!(down, Some((3, chars("cargo test"))));
+    }
+
+    #[test]
+    fn matching() {
+        let entry = chars("cargo build");
+
+        assert_eq!(
+            match_positions(&entry, &chars("go b"), false),
+            Some(vec![3, 4, 5, 6])
+        );
+        assert_eq!(match_positions(&entry, &chars("cb"), false), None);
+        assert_eq!(
+            match_positions(&entry, &chars("cb"), true),
+            Some(vec![0, 6])
+        );
+        assert_eq!(
+            match_positions(&entry, &chars("cbd"), true),
+            Some(vec![0, 6, 10])
+        );
+        // fuzzy matches still have to be in order
+        assert_eq!(match_positions(&entry, &chars("bc"), true), None);
+    }
+
+    #[test]
+    fn escape_sequences() {
+        assert_eq!(escape_sequence("", b'A'), Key::Up);
+        assert_eq!(escape_sequence("", b'D'), Key::Left);
+        assert_eq!(escape_sequence("", b'H'), Key::Home);
+        assert_eq!(escape_sequence("7", b'~'), Key::Home);
+        assert_eq!(escape_sequence("4", b'~'), Key::End);
+        assert_eq!(escape_sequence("3", b'~'), Key::Delete);
+        assert_eq!(escape_sequence("1;5", b'C'), Key::WordRight);
+        assert_eq!(escape_sequence("1;3", b'D'), Key::WordLeft);
+        assert_eq!(escape_sequence("1;2", b'C'), Key::Unknown);
+        assert_eq!(escape_sequence("200", b'~'), Key::Unknown);
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('é'), 1);
+        assert_eq!(char_width('\u{301}'), 0);
+        assert_eq!(char_width('\u{200d}'), 0);
+        assert_eq!(char_width('中'), 2);
+        assert_eq!(char_width('한'), 2);
+        assert_eq!(char_width('\u{1f600}'), 2);
+        // shown as `^J`
+        assert_eq!(char_width('\n'), 2);
+    }
+
+    #[test]
+    fn boundaries_keep_combining_marks_together() {
+        // "e" with a combining acute accent and circumflex
+        let line = line("ae\u{301}\u{302}x", 0);
+
+        assert_eq!(line.next_boundary(0), 1);
+        assert_eq!(line.next_boundary(1), 4);
+        assert_eq!(line.next_boundary(4), 5);
+        assert_eq!(line.next_boundary(5), 5);
+
+        assert_eq!(line.previous_boundary(5), 4);
+        assert_eq!(line.previous_boundary(4), 1);
+        assert_eq!(line.previous_boundary(1), 0);
+        assert_eq!(line.previous_boundary(0), 0);
+    }
+
+    #[test]
+    fn words() {
+        let text = "cd ../café\u{301}/x  y";
+        let word_start = |cursor| line(text, cursor).word_start();
+        let word_end = |cursor| line(text, cursor).word_end();
+
+        assert_eq!(word_start(0), 0);
+        assert_eq!(word_start(2), 0);
+        assert_eq!(word_start(10), 6);
+        assert_eq!(word_start(11), 6);
+        assert_eq!(word_start(16), 15);
+
+        assert_eq!(word_end(0), 2);
+        assert_eq!(word_end(2), 11);
+        // the combining mark is part of the word
+        assert_eq!(word_end(7), 11);
+        assert_eq!(word_end(11), 13);
+        assert_eq!(word_end(13), 16);
+        assert_eq!(word_end(16), 16);
+    }
+}
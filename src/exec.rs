@@ -0,0 +1,367 @@
+use crate::builtins::{self, Builtin, Output, Streams};
+use crate::expand::{expand, expand_argv};
+use crate::parser::{
+    CommandList, Connector, FileMode, Pipeline, RedirectTarget, Redirection, SimpleCommand,
+};
+use crate::shell::Shell;
+use crate::spawn_error::SpawnError;
+use crate::status::Status;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::fs::MetadataExt;
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// Runs each pipeline in the list that its connector allows to run, stopping
+/// early if one of them asks the shell to exit.
+pub fn run_list(shell: &mut Shell, list: &CommandList) {
+    let mut items = list.items.iter().peekable();
+    while let Some((connector, pipeline)) = items.next() {
+        let should_run = match connector {
+            Connector::Always => true,
+            Connector::And => shell.last_status.success(),
+            Connector::Or => !shell.last_status.success(),
+        };
+
+        if should_run {
+            shell.last_status = run_pipeline(shell, pipeline);
+
+            // a failure that a `&&` or `||` is about to check is expected
+            let is_checked = match items.peek() {
+                Some((Connector::And, _)) | Some((Connector::Or, _)) => true,
+                Some((Connector::Always, _)) | None => false,
+            };
+            if shell.errexit && !is_checked && !shell.last_status.success() {
+                shell.exiting = true;
+            }
+        }
+
+        if shell.exiting {
+            return;
+        }
+    }
+}
+
+// A command in a pipeline, after we have tried to start it.
+enum Stage {
+    Running(Child),
+    // builtins, and commands whose redirections failed
+    Finished(Status),
+    // a builtin whose output is still being written to a pipe or file
+    Writing {
+        status: Status,
+        writer: JoinHandle<()>,
+    },
+    // the command could not be started, so the rest of the pipeline should not be
+    NotStarted(Status),
+}
+
+/// Returns the status of the last command in the pipeline, or with `pipefail`
+/// set, the status of the last command that failed.
+pub fn run_pipeline(shell: &mut Shell, pipeline: &Pipeline) -> Status {
+    // this needs to be peekable so we can determine when we are on the last command
+    let mut commands = pipeline.commands.iter().peekable();
+    let mut stages = Vec::with_capacity(pipeline.commands.len());
+    // the read end of the pipe the previous command is writing to, if any
+    let mut previous_output = None;
+
+    while let Some(simple_command) = commands.next() {
+        let mut fds: Fds = [previous_output.take().map(OwnedFd::from), None, None];
+
+        // if there are no more commands piped behind this one, the output goes
+        // to the shell's stdout
+        if commands.peek().is_some() {
+            // there is another command piped behind this one
+            // prepare to send output to the next command
+            match io::pipe() {
+                Ok((reader, writer)) => {
+                    previous_output = Some(reader);
+                    fds[1] = Some(OwnedFd::from(writer));
+                }
+                Err(e) => {
+                    eprintln!("Could not create pipe.\n{}", e);
+                    stages.push(Stage::NotStarted(Status::FAILURE));
+                    break;
+                }
+            }
+        }
+
+        let stage = run_command(shell, simple_command, fds);
+        let should_stop = match stage {
+            Stage::NotStarted(_) => true,
+            _ => shell.exiting,
+        };
+        stages.push(stage);
+
+        if should_stop {
+            break;
+        }
+    }
+
+    // if we stopped early, the earlier commands should see their output is no
+    // longer being read, rather than us waiting on them forever
+    drop(previous_output);
+
+    let statuses: Vec<Status> = stages
+        .into_iter()
+        .map(|stage| match stage {
+            // block until each command has finished, so none are left as zombies
+            Stage::Running(mut child) => match child.wait() {
+                Ok(status) => Status::from(status),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    Status::FAILURE
+                }
+            },
+            Stage::Writing { status, writer } => {
+                // the writer reports its own errors
+                let _ = writer.join();
+                status
+            }
+            Stage::Finished(status) | Stage::NotStarted(status) => status,
+        })
+        .collect();
+
+    let status = if shell.pipefail {
+        statuses.iter().rev().find(|status| !status.success())
+    } else {
+        statuses.last()
+    };
+
+    status.copied().unwrap_or(Status::SUCCESS)
+}
+
+fn run_command(shell: &mut Shell, simple_command: &SimpleCommand, mut fds: Fds) -> Stage {
+    // before any redirections, stdout is only set if it is the pipe to the
+    // next command in the pipeline
+    let pipe_to_next = fds[1].as_ref().and_then(identity);
+
+    if let Err(e) = redirect(&mut fds, &simple_command.redirections, shell) {
+        eprintln!("er: {}", e);
+        return Stage::Finished(Status::FAILURE);
+    }
+
+    let assignments: Result<Vec<(String, String)>, String> = simple_command
+        .assignments
+        .iter()
+        .map(|a| Ok((a.name.clone(), expand(&a.value, shell)?)))
+        .collect();
+    let assignments = match assignments {
+        Ok(assignments) => assignments,
+        Err(e) => {
+            eprintln!("er: {}", e);
+            return Stage::Finished(Status::FAILURE);
+        }
+    };
+
+    // everything after the first word is interpreted as args to the command
+    let mut args = match expand_argv(&simple_command.argv, shell) {
+        Ok(args) => args.into_iter(),
+        Err(e) => {
+            eprintln!("er: {}", e);
+            return Stage::Finished(Status::FAILURE);
+        }
+    };
+    let command = if let Some(command) = args.next() {
+        command
+    } else {
+        // with no command the assignments apply to the shell itself
+        for (name, value) in assignments {
+            env::set_var(name, value);
+        }
+        return Stage::Finished(Status::SUCCESS);
+    };
+
+    let args: Vec<String> = args.collect();
+
+    if let Some(builtin) = builtins::find(&command, &args) {
+        // assignments before a builtin only last as long as it does
+        let previous_values = set_vars(&assignments);
+        let stage = run_builtin(shell, builtin, &args, fds);
+        restore_vars(previous_values);
+
+        return stage;
+    }
+
+    let [stdin, stdout, stderr] = fds;
+    // For saying why the command couldn't be started, to where it would have
+    // written its own errors. Unless that is down the pipe, since the next
+    // command won't be started to pass it on.
+    let error_output = stderr
+        .as_ref()
+        .filter(|fd| pipe_to_next.is_none() || identity(fd) != pipe_to_next)
+        .and_then(|fd| fd.try_clone().ok());
+    let output = Command::new(&command)
+        .args(args)
+        .envs(assignments.iter().map(|(name, value)| (name, value)))
+        .stdin(stdio(stdin))
+        .stdout(stdio(stdout))
+        .stderr(stdio(stderr))
+        .spawn();
+
+    match output {
+        Ok(child) => Stage::Running(child),
+        Err(e) => {
+            // working out what went wrong should see the `PATH` the command would have
+            let previous_values = set_vars(&assignments);
+            let error = SpawnError::new(&command, e, &mut shell.path_index);
+            restore_vars(previous_values);
+
+            let message = format!("er: {}\n", error.with_command(&command));
+            match error_output {
+                Some(fd) => {
+                    let _ = File::from(fd).write_all(message.as_bytes());
+                }
+                None => eprint!("{}", message),
+            }
+            Stage::NotStarted(error.status())
+        }
+    }
+}
+
+// The device and inode of the file `fd` is open to, which are the same for
+// every file descriptor open to the same file or pipe.
+fn identity(fd: &OwnedFd) -> Option<(u64, u64)> {
+    let metadata = File::from(fd.try_clone().ok()?).metadata().ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Returns the previous values of the variables.
+fn set_vars(assignments: &[(String, String)]) -> Vec<(String, Option<OsString>)> {
+    assignments
+        .iter()
+        .map(|(name, value)| {
+            let previous = env::var_os(name);
+            env::set_var(name, value);
+            (name.clone(), previous)
+        })
+        .collect()
+}
+
+fn restore_vars(previous_values: Vec<(String, Option<OsString>)>) {
+    for (name, value) in previous_values {
+        match value {
+            Some(value) => env::set_var(name, value),
+            None => env::remove_var(name),
+        }
+    }
+}
+
+fn run_builtin(shell: &mut Shell, builtin: &dyn Builtin, args: &[String], fds: Fds) -> Stage {
+    let [stdin, stdout, stderr] = fds;
+
+    let stdin = match stdin.map_or_else(|| inherited_fd(0), Ok) {
+        Ok(stdin) => File::from(stdin),
+        Err(e) => {
+            eprintln!("Could not duplicate file descriptor.\n{}", e);
+            return Stage::Finished(Status::FAILURE);
+        }
+    };
+    let mut streams = Streams {
+        stdin,
+        stdout: match stdout {
+            Some(_) => Output::Buffered(Vec::new()),
+            None => Output::Stdout(io::stdout()),
+        },
+        stderr: match stderr {
+            Some(_) => Output::Buffered(Vec::new()),
+            None => Output::Stderr(io::stderr()),
+        },
+    };
+
+    let status = builtin.run(shell, args, &mut streams);
+
+    let mut pending = Vec::new();
+    for (fd, output) in [(stdout, streams.stdout), (stderr, streams.stderr)] {
+        if let (Some(fd), Output::Buffered(bytes)) = (fd, output) {
+            if !bytes.is_empty() {
+                pending.push((File::from(fd), bytes));
+            }
+        }
+    }
+    if pending.is_empty() {
+        return Stage::Finished(status);
+    }
+
+    // the next command in the pipeline has not been started yet, so this has to
+    // happen in the background
+    let writer = thread::spawn(move || {
+        for (mut file, bytes) in pending {
+            match file.write_all(&bytes) {
+                // the reader exiting early is as normal for a builtin as for
+                // anything else
+                Err(e) if e.kind() != io::ErrorKind::BrokenPipe => eprintln!("{}", e),
+                _ => {}
+            }
+        }
+    });
+
+    Stage::Writing { status, writer }
+}
+
+/// The stdin, stdout and stderr a command will be started with. `None` means
+/// the shell's own file descriptor is inherited.
+type Fds = [Option<OwnedFd>; 3];
+
+fn stdio(fd: Option<OwnedFd>) -> Stdio {
+    fd.map_or_else(Stdio::inherit, Stdio::from)
+}
+
+fn redirect(fds: &mut Fds, redirections: &[Redirection], shell: &Shell) -> Result<(), String> {
+    for redirection in redirections {
+        let index = redirection.fd as usize;
+        if index >= fds.len() {
+            return Err(format!(
+                "cannot redirect file descriptor {}, only 0, 1 and 2 are supported",
+                redirection.fd
+            ));
+        }
+
+        let fd = match redirection.target {
+            RedirectTarget::File { ref path, mode } => {
+                let path = expand(path, shell)?;
+                let mut options = OpenOptions::new();
+                match mode {
+                    FileMode::Read => options.read(true),
+                    FileMode::Write => options.write(true).create(true).truncate(true),
+                    FileMode::Append => options.append(true).create(true),
+                };
+
+                options
+                    .open(&path)
+                    .map(OwnedFd::from)
+                    .map_err(|e| format!("{}: {}", path, e))?
+            }
+            RedirectTarget::Fd(target) => {
+                let duplicated = match fds.get(target as usize) {
+                    Some(Some(fd)) => fd.try_clone(),
+                    Some(None) => inherited_fd(target),
+                    None => {
+                        return Err(format!(
+                            "cannot duplicate file descriptor {}, only 0, 1 and 2 are supported",
+                            target
+                        ))
+                    }
+                };
+
+                duplicated
+                    .map_err(|e| format!("could not duplicate file descriptor {}: {}", target, e))?
+            }
+        };
+
+        fds[index] = Some(fd);
+    }
+
+    Ok(())
+}
+
+fn inherited_fd(fd: u32) -> io::Result<OwnedFd> {
+    match fd {
+        0 => io::stdin().as_fd().try_clone_to_owned(),
+        1 => io::stdout().as_fd().try_clone_to_owned(),
+        _ => io::stderr().as_fd().try_clone_to_owned(),
+    }
+}
@@ -0,0 +1,421 @@
+use crate::glob::{self, PatternChar};
+use crate::lexer::{Quoting, Word};
+use crate::shell::Shell;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::mem::MaybeUninit;
+use std::process;
+use std::ptr;
+
+/// Turns a word into the argument a command will see, without treating it as
+/// a pattern.
+pub fn expand(word: &Word, shell: &Shell) -> Result<String, String> {
+    Ok(expand_chars(word, shell)?
+        .into_iter()
+        .map(|p| p.c)
+        .collect())
+}
+
+/// Expands each word into the arguments of a command, including matching
+/// patterns against the filesystem. Unlike `""`, a word with no quotes in it that
+/// expands to nothing, (an unset variable for example,) is left out entirely.
+pub fn expand_argv(words: &[Word], shell: &Shell) -> Result<Vec<String>, String> {
+    let mut argv = Vec::with_capacity(words.len());
+
+    for word in words {
+        let chars = expand_chars(word, shell)?;
+
+        if glob::has_wildcards(&chars) {
+            let matches = glob::expand(&chars);
+            if matches.is_empty() {
+                let pattern: String = chars.iter().map(|p| p.c).collect();
+                return Err(format!("no matches for `{}`", pattern));
+            }
+
+            argv.extend(matches);
+            continue;
+        }
+
+        let is_quoted = word.parts.iter().any(|p| p.quoting != Quoting::Bare);
+        if chars.is_empty() && !is_quoted {
+            continue;
+        }
+
+        argv.push(chars.into_iter().map(|p| p.c).collect());
+    }
+
+    Ok(argv)
+}
+
+fn expand_chars(word: &Word, shell: &Shell) -> Result<Vec<PatternChar>, String> {
+    let mut output = Vec::new();
+
+    for (i, part) in word.parts.iter().enumerate() {
+        match part.quoting {
+            Quoting::Literal => push_str(&mut output, &part.text, false),
+            Quoting::Double => expand_variables(&part.text, shell, false, &mut output)?,
+            Quoting::Bare => {
+                let text = if i == 0 {
+                    let is_whole_word = word.parts.len() == 1;
+                    expand_tilde(&part.text, is_whole_word, &mut output)
+                } else {
+                    &part.text
+                };
+
+                expand_variables(text, shell, true, &mut output)?
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+fn push_str(output: &mut Vec<PatternChar>, s: &str, unquoted: bool) {
+    output.extend(s.chars().map(|c| PatternChar { c, unquoted }));
+}
+
+// Returns the rest of `text` after the `~` or `~user` prefix, if there is one.
+fn expand_tilde<'a>(text: &'a str, is_whole_word: bool, output: &mut Vec<PatternChar>) -> &'a str {
+    if !text.starts_with('~') {
+        return text;
+    }
+
+    let end = match text.find('/') {
+        Some(end) => end,
+        // something quoted right after the user name, like `~"user"`, stops
+        // it from being expanded
+        None if is_whole_word => text.len(),
+        None => return text,
+    };
+
+    let user = &text[1..end];
+    let home = if user.is_empty() {
+        env::var("HOME").ok()
+    } else {
+        home_of(user)
+    };
+
+    match home {
+        Some(home) => {
+            push_str(output, &home, false);
+            &text[end..]
+        }
+        None => text,
+    }
+}
+
+// Asks the C library, so users from NSS sources like LDAP are found, not just
+// the ones in `/etc/passwd`.
+fn home_of(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    let mut entry = MaybeUninit::<libc::passwd>::uninit();
+    let mut result = ptr::null_mut();
+    // the strings the entry points to are stored in here
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+
+    loop {
+        let error = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                entry.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+
+        match error {
+            0 => break,
+            libc::ERANGE if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+            _ => return None,
+        }
+    }
+
+    // a null result means there is no such user, otherwise it points to `entry`
+    if result.is_null() {
+        return None;
+    }
+    let home = unsafe { CStr::from_ptr((*result).pw_dir) };
+
+    Some(home.to_string_lossy().into_owned())
+}
+
+// The characters of variables' values are never treated as pattern syntax.
+fn expand_variables(
+    text: &str,
+    shell: &Shell,
+    unquoted: bool,
+    output: &mut Vec<PatternChar>,
+) -> Result<(), String> {
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((_, c)) = chars.next() {
+        if c != '$' {
+            output.push(PatternChar { c, unquoted });
+            continue;
+        }
+
+        match chars.peek().copied() {
+            Some((_, '?')) | Some((_, '$')) => {
+                if let Some((i, _)) = chars.next() {
+                    push_str(
+                        output,
+                        &lookup(&text[i..i + 1], shell).unwrap_or_default(),
+                        false,
+                    );
+                }
+            }
+            Some((start, '{')) => {
+                // find the matching brace
+                let mut depth = 0;
+                let mut end = None;
+                for (i, c) in chars.by_ref() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                end = Some(i);
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+
+                match end {
+                    Some(end) => expand_braced(&text[start + 1..end], shell, unquoted, output)?,
+                    // the lexer doesn't allow this, but just in case
+                    None => push_str(output, &text[start - 1..], false),
+                }
+            }
+            Some((start, c)) if is_name_start(c) => {
+                let mut end = text.len();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !is_name_char(c) {
+                        end = i;
+                        break;
+                    }
+                    chars.next();
+                }
+
+                push_str(
+                    output,
+                    &lookup(&text[start..end], shell).unwrap_or_default(),
+                    false,
+                );
+            }
+            _ => output.push(PatternChar { c, unquoted }),
+        }
+    }
+
+    Ok(())
+}
+
+// `inner` is the part between the braces of `${...}`
+fn expand_braced(
+    inner: &str,
+    shell: &Shell,
+    unquoted: bool,
+    output: &mut Vec<PatternChar>,
+) -> Result<(), String> {
+    let (name, default) = match inner.find(":-") {
+        Some(i) => (&inner[..i], Some(&inner[i + 2..])),
+        None => (inner, None),
+    };
+
+    if !is_special(name) && !is_name(name) {
+        return Err(format!("${{{}}}: bad substitution", inner));
+    }
+
+    match (lookup(name, shell), default) {
+        (Some(ref value), _) if !value.is_empty() => push_str(output, value, false),
+        (_, Some(default)) => expand_default(default, shell, unquoted, output)?,
+        (_, None) => {}
+    }
+
+    Ok(())
+}
+
+// The lexer keeps the default in `${NAME:-default}` as it was typed, so any
+// quotes and backslashes in it are handled here, the way they would be in a
+// word of their own.
+fn expand_default(
+    text: &str,
+    shell: &Shell,
+    unquoted: bool,
+    output: &mut Vec<PatternChar>,
+) -> Result<(), String> {
+    // text that only needs its variables expanded, up to the next quote
+    let mut pending = String::new();
+    let mut in_double = false;
+
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                expand_variables(&pending, shell, unquoted && !in_double, output)?;
+                pending.clear();
+                in_double = !in_double;
+            }
+            // single quotes inside double ones are just characters
+            '\'' if unquoted && !in_double => {
+                expand_variables(&pending, shell, unquoted, output)?;
+                pending.clear();
+
+                let literal: String = chars.by_ref().take_while(|&c| c != '\'').collect();
+                push_str(output, &literal, false);
+            }
+            '\\' => match chars.next() {
+                Some(escaped) if !in_double || "$`\"\\".contains(escaped) => {
+                    expand_variables(&pending, shell, unquoted && !in_double, output)?;
+                    pending.clear();
+                    output.push(PatternChar {
+                        c: escaped,
+                        unquoted: false,
+                    });
+                }
+                Some(other) => {
+                    pending.push('\\');
+                    pending.push(other);
+                }
+                None => pending.push('\\'),
+            },
+            // a nested `${...}` has quotes of its own to deal with when it is expanded
+            '$' if chars.clone().next() == Some('{') => {
+                pending.push('$');
+                let mut depth = 0;
+                for c in chars.by_ref() {
+                    pending.push(c);
+                    match c {
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            c => pending.push(c),
+        }
+    }
+
+    expand_variables(&pending, shell, unquoted && !in_double, output)
+}
+
+fn lookup(name: &str, shell: &Shell) -> Option<String> {
+    match name {
+        "?" => Some(shell.last_status.code().to_string()),
+        "$" => Some(process::id().to_string()),
+        _ => env::var_os(name).map(|value| value.to_string_lossy().into_owned()),
+    }
+}
+
+fn is_special(name: &str) -> bool {
+    name == "?" || name == "$"
+}
+
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if is_name_start(c) => chars.all(is_name_char),
+        _ => false,
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c == '_' || c.is_ascii_alphabetic()
+}
+
+fn is_name_char(c: char) -> bool {
+    c == '_' || c.is_ascii_alphanumeric()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{tokenize, TokenKind};
+
+    fn words(input: &str) -> Vec<Word> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|token| match token.kind {
+                TokenKind::Word(word) => word,
+                other => panic!("{:?}", other),
+            })
+            .collect()
+    }
+
+    fn argv(input: &str) -> Result<Vec<String>, String> {
+        expand_argv(&words(input), &Shell::new())
+    }
+
+    #[test]
+    fn quoted_wildcards_stay_literal() {
+        assert_eq!(
+            argv(r#"'*' "?" \[a] x'*'"#),
+            Ok(vec![
+                "*".to_string(),
+                "?".to_string(),
+                "[a]".to_string(),
+                "x*".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn patterns_with_no_matches_are_errors() {
+        assert_eq!(
+            argv("echo /er-test-nonexistent/*.txt"),
+            Err("no matches for `/er-test-nonexistent/*.txt`".to_string())
+        );
+    }
+
+    #[test]
+    fn empty_words_are_only_kept_when_quoted() {
+        assert_eq!(
+            argv(r#"a $ER_TEST_UNSET "" '' "$ER_TEST_UNSET""#),
+            Ok(vec![
+                "a".to_string(),
+                String::new(),
+                String::new(),
+                String::new()
+            ])
+        );
+    }
+
+    #[test]
+    fn bad_substitutions_are_errors() {
+        assert_eq!(
+            argv("echo ${a b}/x"),
+            Err("${a b}: bad substitution".to_string())
+        );
+        assert!(argv("echo \"${1x}\"").is_err());
+    }
+
+    #[test]
+    fn tildes() {
+        let expand_one = |input: &str| expand(&words(input)[0], &Shell::new()).unwrap();
+
+        if let Ok(home) = env::var("HOME") {
+            assert_eq!(expand_one("~"), home);
+            assert_eq!(expand_one("~/x"), format!("{}/x", home));
+        }
+
+        let root_home = home_of("root").expect("every system should have root");
+        assert_eq!(expand_one("~root/x"), format!("{}/x", root_home));
+
+        // only an unquoted `~` at the start of a word, before any `/`, counts
+        assert_eq!(
+            expand_one("~er_test_no_such_user/x"),
+            "~er_test_no_such_user/x"
+        );
+        assert_eq!(expand_one("'~'/x"), "~/x");
+        assert_eq!(expand_one("~\"root\""), "~root");
+        assert_eq!(expand_one("a~"), "a~");
+    }
+}
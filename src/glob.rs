@@ -0,0 +1,415 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A character of an expanded word, and whether it was typed without quotes,
+/// which it must have been to have a special meaning in a pattern.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PatternChar {
+    pub c: char,
+    pub unquoted: bool,
+}
+
+impl PatternChar {
+    fn is(self, c: char) -> bool {
+        self.unquoted && self.c == c
+    }
+}
+
+pub fn has_wildcards(pattern: &[PatternChar]) -> bool {
+    pattern
+        .iter()
+        .enumerate()
+        .any(|(i, &p)| p.is('*') || p.is('?') || (p.is('[') && class_end(&pattern[i..]).is_some()))
+}
+
+/// Returns the paths matching `pattern`, sorted. Relative patterns are matched
+/// against the current directory and give relative paths back.
+pub fn expand(pattern: &[PatternChar]) -> Vec<String> {
+    let is_absolute = pattern.first().is_some_and(|p| p.c == '/');
+    let wants_directories = pattern.last().is_some_and(|p| p.c == '/');
+
+    let components: Vec<&[PatternChar]> = pattern
+        .split(|p| p.c == '/')
+        .filter(|component| !component.is_empty())
+        .collect();
+
+    let mut matches = vec![if is_absolute {
+        PathBuf::from("/")
+    } else {
+        PathBuf::new()
+    }];
+
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i + 1 == components.len();
+        let mut next = Vec::new();
+
+        if component.len() == 2 && component.iter().all(|p| p.is('*')) {
+            // `**` matches any number of directories, including none
+            for path in matches.iter() {
+                if !is_last {
+                    next.push(path.clone());
+                }
+                descendants(path, is_last, &mut next);
+            }
+        } else if has_wildcards(component) {
+            let tokens = compile(component);
+            let matches_hidden = component.first().is_some_and(|p| p.c == '.');
+
+            for path in matches.iter() {
+                for name in entry_names(path) {
+                    if name.starts_with('.') && !matches_hidden {
+                        continue;
+                    }
+
+                    let chars: Vec<char> = name.chars().collect();
+                    if is_match(&tokens, &chars) {
+                        next.push(path.join(name));
+                    }
+                }
+            }
+        } else {
+            let name: String = component.iter().map(|p| p.c).collect();
+            for path in matches.iter() {
+                next.push(path.join(&name));
+            }
+        }
+
+        matches = next;
+    }
+
+    let mut results: Vec<String> = matches
+        .into_iter()
+        // literal components were not checked as we went
+        .filter(|path| match fs::metadata(path) {
+            Ok(metadata) => !wants_directories || metadata.is_dir(),
+            // a broken symlink is still something that matched
+            Err(_) => !wants_directories && fs::symlink_metadata(path).is_ok(),
+        })
+        .map(|path| {
+            let mut path = path.to_string_lossy().into_owned();
+            if wants_directories {
+                path.push('/');
+            }
+            path
+        })
+        .collect();
+
+    results.sort();
+    results.dedup();
+    results
+}
+
+fn read_dir_path(path: &Path) -> &Path {
+    if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    }
+}
+
+// names that aren't valid UTF-8 can't be matched, since our patterns are strings
+fn entry_names(path: &Path) -> Vec<String> {
+    match fs::read_dir(read_dir_path(path)) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Symlinks to directories are not followed, to avoid loops.
+fn descendants(path: &Path, include_files: bool, output: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(read_dir_path(path)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let child = path.join(name);
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            output.push(child.clone());
+            descendants(&child, include_files, output);
+        } else if include_files {
+            output.push(child);
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Token {
+    Char(char),
+    // `?`
+    Any,
+    // `*`
+    Star,
+    // `[...]`
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+// `pattern` starts with a `[`. Returns the index of the matching `]`.
+fn class_end(pattern: &[PatternChar]) -> Option<usize> {
+    let mut i = 1;
+    if pattern.get(i).is_some_and(|p| p.c == '!' || p.c == '^') {
+        i += 1;
+    }
+    // a `]` right at the start is part of the class rather than its end
+    if pattern.get(i).is_some_and(|p| p.c == ']') {
+        i += 1;
+    }
+
+    pattern[i..]
+        .iter()
+        .position(|p| p.c == ']')
+        .map(|position| i + position)
+}
+
+fn compile(pattern: &[PatternChar]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    let mut i = 0;
+    while i < pattern.len() {
+        let p = pattern[i];
+        let class_end = if p.is('[') {
+            class_end(&pattern[i..])
+        } else {
+            None
+        };
+
+        if p.is('*') {
+            // consecutive stars match the same things one does
+            if !matches!(tokens.last(), Some(Token::Star)) {
+                tokens.push(Token::Star);
+            }
+        } else if p.is('?') {
+            tokens.push(Token::Any);
+        } else if let Some(end) = class_end {
+            let mut inside = &pattern[i + 1..i + end];
+            let negated = inside.first().is_some_and(|p| p.c == '!' || p.c == '^');
+            if negated {
+                inside = &inside[1..];
+            }
+
+            let mut ranges = Vec::new();
+            let mut j = 0;
+            while j < inside.len() {
+                let start = inside[j].c;
+                match (inside.get(j + 1), inside.get(j + 2)) {
+                    (Some(dash), Some(end)) if dash.c == '-' => {
+                        ranges.push((start, end.c));
+                        j += 3;
+                    }
+                    _ => {
+                        ranges.push((start, start));
+                        j += 1;
+                    }
+                }
+            }
+
+            tokens.push(Token::Class { negated, ranges });
+            i += end;
+        } else {
+            tokens.push(Token::Char(p.c));
+        }
+
+        i += 1;
+    }
+
+    tokens
+}
+
+// When what comes after a `*` stops matching, only the most recent `*` needs
+// to try taking one more character, since any earlier one taking more can
+// only lead to the same places. That keeps this to O(tokens × name).
+fn is_match(tokens: &[Token], name: &[char]) -> bool {
+    let (mut t, mut n) = (0, 0);
+    // the index of the last `*` seen, and of the first character it hasn't taken
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match tokens.get(t) {
+            Some(Token::Star) => {
+                backtrack = Some((t, n));
+                t += 1;
+                continue;
+            }
+            Some(token) if token_matches(token, name[n]) => {
+                t += 1;
+                n += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        match backtrack {
+            Some((star, taken)) => {
+                backtrack = Some((star, taken + 1));
+                t = star + 1;
+                n = taken + 1;
+            }
+            None => return false,
+        }
+    }
+
+    tokens[t..].iter().all(|token| matches!(token, Token::Star))
+}
+
+fn token_matches(token: &Token, c: char) -> bool {
+    match *token {
+        Token::Char(expected) => c == expected,
+        Token::Any => true,
+        Token::Class {
+            negated,
+            ref ranges,
+        } => negated != ranges.iter().any(|&(low, high)| low <= c && c <= high),
+        Token::Star => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use std::time::{Duration, Instant};
+
+    fn pattern(s: &str) -> Vec<PatternChar> {
+        s.chars()
+            .map(|c| PatternChar { c, unquoted: true })
+            .collect()
+    }
+
+    fn matches(p: &str, name: &str) -> bool {
+        let name: Vec<char> = name.chars().collect();
+        is_match(&compile(&pattern(p)), &name)
+    }
+
+    #[test]
+    fn stars_and_question_marks() {
+        assert!(matches("*.txt", "a.txt"));
+        assert!(matches("*.txt", ".txt"));
+        assert!(!matches("*.txt", "a.txt.bak"));
+        assert!(matches("a*b*c", "abc"));
+        assert!(matches("a*b*c", "axxbyybc"));
+        assert!(!matches("a*b*c", "axxbyy"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("**", ""));
+    }
+
+    #[test]
+    fn many_stars_are_quick() {
+        let name = "a".repeat(40);
+        let started = Instant::now();
+
+        assert!(!matches("*a*a*a*a*a*a*a*a*b", &name));
+        assert!(matches("*a*a*a*a*a*a*a*a*", &name));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[!a-c]x", "dx"));
+        assert!(matches("[^a-c]x", "dx"));
+        assert!(matches("[xa-c]", "x"));
+
+        // a `]` right at the start is part of the class
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[]a]", "a"));
+        assert!(!matches("[!]]", "]"));
+        assert!(matches("[!]]", "x"));
+    }
+
+    #[test]
+    fn class_ends() {
+        assert_eq!(class_end(&pattern("[abc]")), Some(4));
+        assert_eq!(class_end(&pattern("[]a]")), Some(3));
+        assert_eq!(class_end(&pattern("[!]a]")), Some(4));
+        assert_eq!(class_end(&pattern("[abc")), None);
+
+        // an unclosed `[` is just a character
+        assert!(!has_wildcards(&pattern("[abc")));
+        assert!(matches("[ab*", "[abc"));
+    }
+
+    #[test]
+    fn quoted_wildcards_are_literal() {
+        let quoted: Vec<PatternChar> = "*?[a]"
+            .chars()
+            .map(|c| PatternChar { c, unquoted: false })
+            .collect();
+
+        assert!(!has_wildcards(&quoted));
+        assert!(is_match(&compile(&quoted), &['*', '?', '[', 'a', ']']));
+        assert!(!is_match(&compile(&quoted), &['x', 'y', 'a']));
+    }
+
+    // Matches `pattern` in a fresh directory, giving back the paths relative to it.
+    fn expand_in(dir: &Path, pattern_in_dir: &str) -> Vec<String> {
+        let prefix = format!("{}/", dir.display());
+        expand(&pattern(&format!("{}{}", prefix, pattern_in_dir)))
+            .into_iter()
+            .map(|path| path[prefix.len()..].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn expanding_against_the_filesystem() {
+        let dir = env::temp_dir().join(format!("er-glob-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for directory in ["sub/deep", "sub/.secret"] {
+            fs::create_dir_all(dir.join(directory)).unwrap();
+        }
+        for file in [
+            ".hidden",
+            "a.txt",
+            "b.txt",
+            "c.rs",
+            "sub/d.txt",
+            "sub/deep/e.txt",
+            "sub/.secret/f.txt",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        assert_eq!(expand_in(&dir, "*"), vec!["a.txt", "b.txt", "c.rs", "sub"]);
+        // hidden files only match a pattern that starts with a `.`
+        assert_eq!(expand_in(&dir, ".*"), vec![".hidden"]);
+        assert_eq!(expand_in(&dir, "?hidden"), Vec::<String>::new());
+        assert_eq!(expand_in(&dir, "[!a]*.txt"), vec!["b.txt"]);
+
+        // a trailing `/` only matches directories, and keeps the `/`
+        assert_eq!(expand_in(&dir, "*/"), vec!["sub/"]);
+
+        // `**` in the middle can also match no directories at all
+        assert_eq!(
+            expand_in(&dir, "**/*.txt"),
+            vec!["a.txt", "b.txt", "sub/d.txt", "sub/deep/e.txt"]
+        );
+        assert_eq!(
+            expand_in(&dir, "sub/**/*.txt"),
+            vec!["sub/d.txt", "sub/deep/e.txt"]
+        );
+        // and at the end matches everything below, without the directory itself
+        assert_eq!(
+            expand_in(&dir, "sub/**"),
+            vec!["sub/d.txt", "sub/deep", "sub/deep/e.txt"]
+        );
+
+        assert_eq!(expand_in(&dir, "*.none"), Vec::<String>::new());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
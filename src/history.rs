@@ -0,0 +1,512 @@
+use crate::status::Status;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem::MaybeUninit;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// The first line of a history file, followed by the version of the format.
+// Files without it are from before there was a format, and have one command
+// per line.
+const HEADER: &str = "#er-history";
+const VERSION: u32 = 1;
+
+// added to the history file's name to make the name of a file to write to before
+// replacing the history file with it
+const TEMP_SUFFIX: &str = "_temp";
+// added to the history file's name to make the name of the file `Lock` uses
+const LOCK_SUFFIX: &str = "_lock";
+
+/// A command entered interactively. Everything but the command itself is
+/// unknown for entries from history files written before they were recorded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub command: String,
+    pub started: Option<SystemTime>,
+    // the current directory when the command started
+    pub directory: Option<PathBuf>,
+    // these are only known once the command has finished
+    pub duration: Option<Duration>,
+    pub status: Option<Status>,
+}
+
+impl Entry {
+    /// An entry for a command that is starting now.
+    pub fn new(command: String) -> Self {
+        Entry {
+            command,
+            started: Some(SystemTime::now()),
+            directory: env::current_dir().ok(),
+            duration: None,
+            status: None,
+        }
+    }
+}
+
+/// The entries read from a history file.
+pub struct Loaded {
+    pub entries: Vec<Entry>,
+    // Set if the file could be read, but not locked, like when it is in a
+    // directory we can't write to. Adding to it isn't safe then.
+    pub lock_error: Option<io::Error>,
+}
+
+/// Reads the history file at `path`, merging what different instances added to
+/// it into the order the commands were started in.
+pub fn load(path: &Path) -> io::Result<Loaded> {
+    // the file can still be read without the lock, just not safely added to
+    let (_lock, lock_error) = match Lock::new(path) {
+        Ok(lock) => (Some(lock), None),
+        Err(e) => (None, Some(e)),
+    };
+    let text = fs::read_to_string(path)?;
+
+    let mut lines = text.lines().peekable();
+    let mut entries: Vec<Entry> = match version(lines.peek().copied().unwrap_or_default())? {
+        Some(_) => {
+            lines.next();
+            // a line cut short, by running out of disk space for example, is
+            // skipped rather than costing us the rest of the history
+            lines.filter_map(parse_entry).collect()
+        }
+        None => parse_plain(lines),
+    };
+
+    // Instances add each command when it finishes, so one that ran for a long
+    // time comes after others started later, as do the commands of a session
+    // that was running alongside another one. Sorting is stable, so entries
+    // with no start time stay where they were, which is at the start.
+    entries.sort_by_key(|entry| entry.started);
+
+    Ok(Loaded {
+        entries,
+        lock_error,
+    })
+}
+
+/// Adds `entry` to the end of the history file at `path`, creating the file if
+/// needed. Other instances can be adding to the same file at the same time.
+pub fn append(path: &Path, entry: &Entry) -> io::Result<()> {
+    let _lock = Lock::new(path)?;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+
+    let mut first_line = String::new();
+    BufReader::new(&file).read_line(&mut first_line)?;
+
+    let mut line = format_entry(entry);
+    line.push('\n');
+    if first_line.is_empty() {
+        line.insert_str(0, &format!("{} {}\n", HEADER, VERSION));
+    } else if version(&first_line)?.is_none() {
+        // this is the first entry since an older version of `er` saved the
+        // file, so it needs converting to something we can add to
+        let mut entries = parse_plain(fs::read_to_string(path)?.lines());
+        entries.push(entry.clone());
+        return rewrite(path, &entries);
+    } else {
+        // a line cut short shouldn't take this entry with it
+        let mut last = [0];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            line.insert(0, '\n');
+        }
+    }
+
+    // in one write, so a failure can't leave more than one line cut short
+    file.write_all(line.as_bytes())
+}
+
+// Replaces the file at `path` with `entries`. The file is written under another
+// name first, so the history isn't lost if writing fails part way.
+fn rewrite(path: &Path, entries: &[Entry]) -> io::Result<()> {
+    let temp_path = with_suffix(path, TEMP_SUFFIX);
+
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    writeln!(writer, "{} {}", HEADER, VERSION)?;
+    for entry in entries {
+        writeln!(writer, "{}", format_entry(entry))?;
+    }
+    writer.flush()?;
+    drop(writer);
+
+    fs::rename(temp_path, path)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+// Held while reading or adding to the history file, so instances running at the
+// same time take turns. It is a separate file so that when the history file is
+// replaced, no one can be left holding a lock on the old one.
+struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    fn new(history_path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(with_suffix(history_path, LOCK_SUFFIX))?;
+        // this blocks until any other instance is done, and the lock is released
+        // when the file is closed
+        file.lock()?;
+
+        Ok(Lock { _file: file })
+    }
+}
+
+// Returns the version of the format the file is in, given its first line, or
+// `None` if it is from before there was a format.
+fn version(first_line: &str) -> io::Result<Option<u32>> {
+    let version = match first_line.strip_prefix(HEADER) {
+        Some(version) => version.trim(),
+        None => return Ok(None),
+    };
+
+    let message = match version.parse::<u32>() {
+        Ok(version) if version <= VERSION => return Ok(Some(version)),
+        // rather than risk adding to, or converting, what a newer version saved
+        Ok(version) => format!(
+            "the history is in version {} of the format, but only versions up to {} are supported",
+            version, VERSION
+        ),
+        Err(_) => format!("unrecognised history header `{}`", version),
+    };
+
+    Err(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+// one command per line, with nothing else known about them
+fn parse_plain<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<Entry> {
+    lines
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty())
+        .map(|command| Entry {
+            command: command.to_string(),
+            started: None,
+            directory: None,
+            duration: None,
+            status: None,
+        })
+        .collect()
+}
+
+// Each entry is on one line, with tab separated fields in this order:
+//     start time, in milliseconds since the Unix epoch
+//     duration, in milliseconds
+//     exit status, as a number, or `signal:N` or `signal:N:core`
+//     working directory
+//     command
+// Fields that aren't known are left empty, and tabs, newlines and backslashes
+// in them are escaped with a backslash.
+fn format_entry(entry: &Entry) -> String {
+    let started = entry
+        .started
+        .and_then(|started| started.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_millis().to_string())
+        .unwrap_or_default();
+    let duration = entry
+        .duration
+        .map(|duration| duration.as_millis().to_string())
+        .unwrap_or_default();
+    let status = match entry.status {
+        Some(Status::Exited(code)) => code.to_string(),
+        Some(Status::Signaled {
+            signal,
+            core_dumped,
+        }) => format!(
+            "signal:{}{}",
+            signal,
+            if core_dumped { ":core" } else { "" }
+        ),
+        None => String::new(),
+    };
+    let directory = entry
+        .directory
+        .as_ref()
+        .map(|directory| directory.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    [
+        started,
+        duration,
+        status,
+        escape(&directory),
+        escape(&entry.command),
+    ]
+    .join("\t")
+}
+
+fn parse_entry(line: &str) -> Option<Entry> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let (started, duration, status, directory, command) = match fields[..] {
+        [started, duration, status, directory, command] => {
+            (started, duration, status, directory, command)
+        }
+        _ => return None,
+    };
+
+    fn optional<T>(field: &str, parse: impl Fn(&str) -> Option<T>) -> Option<Option<T>> {
+        if field.is_empty() {
+            Some(None)
+        } else {
+            parse(field).map(Some)
+        }
+    }
+
+    Some(Entry {
+        command: unescape(command)?,
+        started: optional(started, |s| {
+            Some(UNIX_EPOCH + Duration::from_millis(s.parse().ok()?))
+        })?,
+        directory: optional(directory, |s| Some(PathBuf::from(unescape(s)?)))?,
+        duration: optional(duration, |s| Some(Duration::from_millis(s.parse().ok()?)))?,
+        status: optional(status, parse_status)?,
+    })
+}
+
+fn parse_status(field: &str) -> Option<Status> {
+    let signal = match field.strip_prefix("signal:") {
+        Some(signal) => signal,
+        None => return field.parse().ok().map(Status::Exited),
+    };
+
+    let (signal, core_dumped) = match signal.strip_suffix(":core") {
+        Some(signal) => (signal, true),
+        None => (signal, false),
+    };
+
+    Some(Status::Signaled {
+        signal: signal.parse().ok()?,
+        core_dumped,
+    })
+}
+
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        unescaped.push(match chars.next()? {
+            '\\' => '\\',
+            't' => '\t',
+            'n' => '\n',
+            'r' => '\r',
+            _ => return None,
+        });
+    }
+    Some(unescaped)
+}
+
+/// Formats `time` as a date and time in the local time zone.
+pub fn format_time(time: SystemTime) -> String {
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => since_epoch.as_secs() as libc::time_t,
+        Err(_) => 0,
+    };
+
+    let mut local = MaybeUninit::<libc::tm>::uninit();
+    if unsafe { libc::localtime_r(&seconds, local.as_mut_ptr()) }.is_null() {
+        return "?".to_string();
+    }
+    let local = unsafe { local.assume_init() };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        local.tm_year + 1900,
+        local.tm_mon + 1,
+        local.tm_mday,
+        local.tm_hour,
+        local.tm_min,
+        local.tm_sec
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+    use std::thread;
+
+    // a fresh directory for each test, so they can run at the same time
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("er-history-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry(command: String, started_millis: u64) -> Entry {
+        Entry {
+            command,
+            started: Some(UNIX_EPOCH + Duration::from_millis(started_millis)),
+            directory: Some(PathBuf::from("/some\tdir")),
+            duration: Some(Duration::from_millis(started_millis % 7)),
+            status: Some(if started_millis.is_multiple_of(2) {
+                Status::Exited(1)
+            } else {
+                Status::Signaled {
+                    signal: 9,
+                    core_dumped: true,
+                }
+            }),
+        }
+    }
+
+    #[test]
+    fn concurrent_appends_are_merged_in_start_order() {
+        const INSTANCES: u64 = 4;
+        const COMMANDS: u64 = 50;
+
+        let dir = temp_dir("concurrent");
+        let path = dir.join("er_history");
+
+        let threads: Vec<_> = (0..INSTANCES)
+            .map(|instance| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    for i in 0..COMMANDS {
+                        // the instances' commands started interleaved with each
+                        // other's, but are added whenever each thread gets to them
+                        let started = i * INSTANCES + instance;
+                        let command = format!("echo {} {}\n", instance, i);
+                        append(&path, &entry(command, started)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let loaded = load(&path).unwrap();
+        assert!(loaded.lock_error.is_none());
+        let expected: Vec<Entry> = (0..INSTANCES * COMMANDS)
+            .map(|started| {
+                let command = format!("echo {} {}\n", started % INSTANCES, started / INSTANCES);
+                entry(command, started)
+            })
+            .collect();
+        assert_eq!(loaded.entries, expected);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn loading_while_others_append_only_sees_whole_entries() {
+        const INSTANCES: u64 = 4;
+        const COMMANDS: u64 = 200;
+
+        let dir = temp_dir("loading");
+        let path = dir.join("er_history");
+        // so there is something to load from the start
+        append(&path, &entry("ls".to_string(), 0)).unwrap();
+
+        let threads: Vec<_> = (0..INSTANCES)
+            .map(|instance| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    for i in 0..COMMANDS {
+                        let started = 1 + i * INSTANCES + instance;
+                        let command = format!("echo {} {}", instance, i);
+                        append(&path, &entry(command, started)).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let mut loaded_count = 0;
+        while threads.iter().any(|thread| !thread.is_finished()) {
+            let loaded = load(&path).unwrap();
+            assert!(loaded.lock_error.is_none());
+            assert!(loaded.entries.len() >= loaded_count);
+            loaded_count = loaded.entries.len();
+
+            // each instance's entries so far, exactly as they were added
+            let mut next = [0; INSTANCES as usize];
+            for loaded in &loaded.entries[1..] {
+                let instance = loaded.command.split(' ').nth(1).unwrap();
+                let instance: usize = instance.parse().unwrap();
+                let started = 1 + next[instance] * INSTANCES + instance as u64;
+                let command = format!("echo {} {}", instance, next[instance]);
+                assert_eq!(*loaded, entry(command, started));
+                next[instance] += 1;
+            }
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let loaded = load(&path).unwrap();
+        assert_eq!(loaded.entries.len() as u64, 1 + INSTANCES * COMMANDS);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn plain_history_is_converted_when_added_to() {
+        let dir = temp_dir("plain");
+        let path = dir.join("er_history");
+        fs::write(&path, "ls\r\n\ncargo build\n").unwrap();
+
+        append(&path, &entry("ls -a".to_string(), 5)).unwrap();
+
+        let commands: Vec<String> = load(&path)
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect();
+        assert_eq!(commands, vec!["ls", "cargo build", "ls -a"]);
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("#er-history 1\n"));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn newer_versions_are_not_touched() {
+        let dir = temp_dir("newer");
+        let path = dir.join("er_history");
+        fs::write(&path, "#er-history 2\nwhatever\n").unwrap();
+
+        assert!(load(&path).is_err());
+        assert!(append(&path, &entry("ls".to_string(), 1)).is_err());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "#er-history 2\nwhatever\n"
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,512 @@
+use std::fmt;
+
+/// How a piece of a word was written, which decides what later stages,
+/// (expansion for example,) are allowed to do to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quoting {
+    Bare,
+    // Single quotes, or a backslash escape.
+    Literal,
+    Double,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WordPart {
+    pub text: String,
+    pub quoting: Quoting,
+}
+
+/// A single argument, possibly made out of several differently quoted parts,
+/// like `--message="a b"'c'`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    fn push(&mut self, c: char, quoting: Quoting) {
+        match self.parts.last_mut() {
+            Some(part) if part.quoting == quoting => part.text.push(c),
+            _ => self.parts.push(WordPart {
+                text: c.to_string(),
+                quoting,
+            }),
+        }
+    }
+
+    fn push_empty(&mut self, quoting: Quoting) {
+        self.parts.push(WordPart {
+            text: String::new(),
+            quoting,
+        });
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for part in self.parts.iter() {
+            f.write_str(&part.text)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Word(Word),
+    // `&&`
+    And,
+    // `||`
+    Or,
+    // `;`
+    Semicolon,
+    // `|`
+    Pipe,
+    // `|&`, which pipes stderr along with stdout
+    PipeAnd,
+    Redirect {
+        // the explicit file descriptor number before the operator, like the 2 in `2>`
+        fd: Option<u32>,
+        op: RedirectOp,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedirectOp {
+    // `<`
+    Read,
+    // `>`
+    Write,
+    // `>>`
+    Append,
+    // `>&`
+    Duplicate,
+    // `&>`
+    WriteAll,
+    // `&>>`
+    AppendAll,
+}
+
+impl fmt::Display for RedirectOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RedirectOp::Read => "<",
+            RedirectOp::Write => ">",
+            RedirectOp::Append => ">>",
+            RedirectOp::Duplicate => ">&",
+            RedirectOp::WriteAll => "&>",
+            RedirectOp::AppendAll => "&>>",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    // in chars, from the start of the line
+    pub column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LexErrorKind {
+    UnterminatedSingleQuote,
+    UnterminatedDoubleQuote,
+    UnterminatedBrace,
+    // a backslash at the very end, with no newline after it to escape
+    TrailingBackslash,
+    // a backslash and newline at the end, so the next line is part of this one
+    ContinuedLine,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub column: usize,
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LexErrorKind::UnterminatedSingleQuote => "unterminated single quote",
+            LexErrorKind::UnterminatedDoubleQuote => "unterminated double quote",
+            LexErrorKind::UnterminatedBrace => "unterminated `${`",
+            LexErrorKind::TrailingBackslash => "backslash at end of input",
+            LexErrorKind::ContinuedLine => "line continues past end of input",
+        })
+    }
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().enumerate().peekable();
+
+    // `None` when we are between words
+    let mut current: Option<(Word, usize)> = None;
+
+    macro_rules! word {
+        ($column: expr) => {
+            &mut current.get_or_insert_with(|| (Word::default(), $column)).0
+        };
+    }
+
+    macro_rules! end_word {
+        () => {
+            if let Some((word, column)) = current.take() {
+                tokens.push(Token {
+                    kind: TokenKind::Word(word),
+                    column,
+                });
+            }
+        };
+    }
+
+    macro_rules! operator {
+        ($kind: expr, $column: expr) => {{
+            end_word!();
+            tokens.push(Token {
+                kind: $kind,
+                column: $column,
+            });
+        }};
+    }
+
+    macro_rules! next_is {
+        ($c: expr) => {
+            if chars.peek().map(|&(_, c)| c) == Some($c) {
+                chars.next();
+                true
+            } else {
+                false
+            }
+        };
+    }
+
+    // expects the first `<` or `>` to have been consumed already
+    macro_rules! redirect {
+        ($first: expr, $fd: expr, $column: expr) => {{
+            let op = if $first == '<' {
+                RedirectOp::Read
+            } else if next_is!('>') {
+                RedirectOp::Append
+            } else if next_is!('&') {
+                RedirectOp::Duplicate
+            } else {
+                RedirectOp::Write
+            };
+
+            operator!(TokenKind::Redirect { fd: $fd, op }, $column)
+        }};
+    }
+
+    while let Some((column, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => end_word!(),
+            // a comment runs to the end of the line
+            '#' if current.is_none() => {
+                while let Some(&(_, c)) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            ';' => operator!(TokenKind::Semicolon, column),
+            '&' if next_is!('&') => operator!(TokenKind::And, column),
+            '&' if next_is!('>') => {
+                let op = if next_is!('>') {
+                    RedirectOp::AppendAll
+                } else {
+                    RedirectOp::WriteAll
+                };
+                operator!(TokenKind::Redirect { fd: None, op }, column)
+            }
+            '<' | '>' => redirect!(c, None, column),
+            // a lone digit right before a redirection is the file descriptor to redirect
+            c if current.is_none()
+                && c.is_ascii_digit()
+                && matches!(chars.peek(), Some(&(_, '<')) | Some(&(_, '>'))) =>
+            {
+                let fd = c.to_digit(10);
+                if let Some((_, first)) = chars.next() {
+                    redirect!(first, fd, column)
+                }
+            }
+            '|' if next_is!('|') => operator!(TokenKind::Or, column),
+            '|' if next_is!('&') => operator!(TokenKind::PipeAnd, column),
+            '|' => operator!(TokenKind::Pipe, column),
+            '\\' => match chars.next() {
+                // a backslash before a newline continues the line, so the
+                // input isn't finished if that is where it ends
+                Some((_, '\n')) if chars.peek().is_none() => {
+                    return Err(LexError {
+                        kind: LexErrorKind::ContinuedLine,
+                        column,
+                    })
+                }
+                Some((_, '\n')) => {}
+                Some((_, escaped)) => word!(column).push(escaped, Quoting::Literal),
+                None => {
+                    return Err(LexError {
+                        kind: LexErrorKind::TrailingBackslash,
+                        column,
+                    })
+                }
+            },
+            '\'' => {
+                let word = word!(column);
+                word.push_empty(Quoting::Literal);
+                loop {
+                    match chars.next() {
+                        Some((_, '\'')) => break,
+                        Some((_, c)) => word.push(c, Quoting::Literal),
+                        None => {
+                            return Err(LexError {
+                                kind: LexErrorKind::UnterminatedSingleQuote,
+                                column,
+                            })
+                        }
+                    }
+                }
+            }
+            '"' => {
+                let word = word!(column);
+                word.push_empty(Quoting::Double);
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.peek() {
+                            Some(&(_, '\n')) => {
+                                chars.next();
+                            }
+                            Some(&(_, escaped)) if "$`\"\\".contains(escaped) => {
+                                chars.next();
+                                word.push(escaped, Quoting::Literal);
+                            }
+                            _ => word.push('\\', Quoting::Double),
+                        },
+                        Some((dollar_column, '$'))
+                            if chars.peek().map(|&(_, c)| c) == Some('{') =>
+                        {
+                            braced_expansion(&mut chars, word, Quoting::Double, dollar_column)?
+                        }
+                        Some((_, c)) => word.push(c, Quoting::Double),
+                        None => {
+                            return Err(LexError {
+                                kind: LexErrorKind::UnterminatedDoubleQuote,
+                                column,
+                            })
+                        }
+                    }
+                }
+            }
+            '$' if chars.peek().map(|&(_, c)| c) == Some('{') => {
+                braced_expansion(&mut chars, word!(column), Quoting::Bare, column)?
+            }
+            c => word!(column).push(c, Quoting::Bare),
+        }
+    }
+
+    end_word!();
+
+    Ok(tokens)
+}
+
+// `${...}` is kept together even if it contains spaces, so expansion can see
+// all of it. Expects the `$` to have been consumed already.
+fn braced_expansion<I>(
+    chars: &mut I,
+    word: &mut Word,
+    quoting: Quoting,
+    column: usize,
+) -> Result<(), LexError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    word.push('$', quoting);
+
+    let mut depth = 0;
+    for (_, c) in chars {
+        word.push(c, quoting);
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Err(LexError {
+        kind: LexErrorKind::UnterminatedBrace,
+        column,
+    })
+}
+
+/// Quotes `s` so that `tokenize` would turn it back into a single word with
+/// the same text, adding no quotes if none are needed.
+pub fn quote(s: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "_-./:@%+=,".contains(c);
+
+    if !s.is_empty() && s.chars().all(is_plain) {
+        return s.to_string();
+    }
+
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect()
+    }
+
+    fn bare(text: &str) -> TokenKind {
+        TokenKind::Word(Word {
+            parts: vec![WordPart {
+                text: text.to_string(),
+                quoting: Quoting::Bare,
+            }],
+        })
+    }
+
+    #[test]
+    fn quoted_parts_stay_in_one_word() {
+        let tokens = kinds(r#"echo 'a b'"c d"\ e"#);
+        let part = |text: &str, quoting| WordPart {
+            text: text.to_string(),
+            quoting,
+        };
+
+        assert_eq!(
+            tokens,
+            vec![
+                bare("echo"),
+                TokenKind::Word(Word {
+                    parts: vec![
+                        part("a b", Quoting::Literal),
+                        part("c d", Quoting::Double),
+                        part(" ", Quoting::Literal),
+                        part("e", Quoting::Bare),
+                    ]
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn operators_inside_quotes_are_text() {
+        assert_eq!(kinds("'a|b' \"&&\"").len(), 2);
+    }
+
+    #[test]
+    fn pipes_need_no_spaces() {
+        assert_eq!(
+            kinds("a|b|&c||d"),
+            vec![
+                bare("a"),
+                TokenKind::Pipe,
+                bare("b"),
+                TokenKind::PipeAnd,
+                bare("c"),
+                TokenKind::Or,
+                bare("d"),
+            ]
+        );
+    }
+
+    #[test]
+    fn redirections() {
+        let redirect = |fd, op| TokenKind::Redirect { fd, op };
+        assert_eq!(
+            kinds("a 2>err >>out 2>&1 &>all <in x2>y"),
+            vec![
+                bare("a"),
+                redirect(Some(2), RedirectOp::Write),
+                bare("err"),
+                redirect(None, RedirectOp::Append),
+                bare("out"),
+                redirect(Some(2), RedirectOp::Duplicate),
+                bare("1"),
+                redirect(None, RedirectOp::WriteAll),
+                bare("all"),
+                redirect(None, RedirectOp::Read),
+                bare("in"),
+                // only a lone digit is a file descriptor
+                bare("x2"),
+                redirect(None, RedirectOp::Write),
+                bare("y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn columns_count_chars() {
+        let columns: Vec<usize> = tokenize("é  ab|c")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.column)
+            .collect();
+        assert_eq!(columns, vec![0, 3, 5, 6]);
+    }
+
+    #[test]
+    fn comments_run_to_the_end_of_the_line() {
+        assert_eq!(kinds("a # b c\nd"), vec![bare("a"), bare("d")]);
+        assert_eq!(kinds("a#b"), vec![bare("a#b")]);
+    }
+
+    #[test]
+    fn unterminated_quotes() {
+        assert_eq!(
+            tokenize("echo 'a").unwrap_err(),
+            LexError {
+                kind: LexErrorKind::UnterminatedSingleQuote,
+                column: 5,
+            }
+        );
+        assert_eq!(
+            tokenize("echo a \"b").unwrap_err().kind,
+            LexErrorKind::UnterminatedDoubleQuote
+        );
+    }
+
+    #[test]
+    fn backslash_newline_joins_lines() {
+        assert_eq!(
+            kinds("echo a \\\nb\n"),
+            vec![bare("echo"), bare("a"), bare("b")]
+        );
+        assert_eq!(
+            tokenize("echo a \\\n").unwrap_err(),
+            LexError {
+                kind: LexErrorKind::ContinuedLine,
+                column: 7,
+            }
+        );
+        assert_eq!(
+            tokenize("echo a \\").unwrap_err().kind,
+            LexErrorKind::TrailingBackslash
+        );
+    }
+
+    #[test]
+    fn quote_round_trips() {
+        for s in ["plain", "", "a b", "it's", "$HOME", "a\nb"] {
+            let tokens = kinds(&quote(s));
+            assert_eq!(tokens.len(), 1, "{:?}", s);
+            match &tokens[0] {
+                TokenKind::Word(word) => assert_eq!(word.to_string(), s),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+}
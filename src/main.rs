@@ -1,16 +1,78 @@
+mod builtins;
+mod cli;
+mod dirs;
+mod editor;
+mod exec;
+mod expand;
+mod glob;
+mod history;
+mod lexer;
+mod parser;
+mod path;
+mod script;
+mod shell;
+mod spawn_error;
+mod status;
+
+use cli::{Action, Mode, Options};
+use shell::Shell;
+use status::Status;
 use std::env;
-use std::fs::{self, File};
-use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::fs;
+use std::io::{self, stdout, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Child, Command, Stdio};
+use std::process;
+use std::time::Instant;
 
-const HISTORY_NAME: &'static str = "er_history";
-const TEMP_HISTORY_NAME: &'static str = "er_history_temp";
+const HISTORY_NAME: &str = "er_history";
 
 fn main() {
-    print!("er - executable runner v{}\n\n", env!("CARGO_PKG_VERSION"));
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(Action::Run(options)) => options,
+        // if stdout is closed there's no one to tell, so we ignore write errors here
+        Ok(Action::PrintHelp) => {
+            let _ = writeln!(stdout(), "{}", cli::USAGE);
+            return;
+        }
+        Ok(Action::PrintVersion) => {
+            let _ = writeln!(stdout(), "er {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(e) => {
+            eprintln!("er: {}\nTry `er --help` for more information.", e);
+            process::exit(2);
+        }
+    };
+
+    let mut shell = Shell::new();
+    shell.errexit = options.errexit;
+
+    match options.mode {
+        Mode::Interactive => interactive(&mut shell, &options),
+        Mode::Command(ref command) => script::run(&mut shell, "-c", command),
+        Mode::Script(ref path) => match fs::read_to_string(path) {
+            Ok(source) => script::run(&mut shell, &path.display().to_string(), &source),
+            Err(e) => {
+                eprintln!("er: {}: {}", path.display(), e);
+                shell.last_status = Status::NOT_FOUND;
+            }
+        },
+    }
+
+    process::exit(shell.last_status.code());
+}
+
+fn interactive(shell: &mut Shell, options: &Options) {
+    if !options.quiet {
+        print!("er - executable runner v{}\n\n", env!("CARGO_PKG_VERSION"));
+    }
+
+    if options.no_history {
+        command_loop(shell, None);
+        return;
+    }
 
-    let mut history_path = match env::current_exe().map(|mut p| {
+    let default_history_path = || match env::current_exe().map(|mut p| {
         p.pop();
         p.push(HISTORY_NAME);
         p
@@ -21,189 +83,166 @@ fn main() {
                 "Could locate executable path. Using working directory.\n{}",
                 e
             );
-            PathBuf::from(".")
+            PathBuf::from(HISTORY_NAME)
         }
     };
-
-    let mut should_not_save_history = false;
-
-    let mut history = {
-        let result = File::open(&history_path)
-            .map(BufReader::new)
-            .and_then(|mut f| {
-                let mut v = Vec::with_capacity(f.by_ref().lines().count());
-
-                f.seek(SeekFrom::Start(0))?;
-                for line in f.lines() {
-                    let line = line?;
-                    v.push(line);
-                }
-
-                Ok(v)
-            });
-
-        match result {
-            Ok(v) => v,
-            Err(e) => {
-                should_not_save_history = false;
+    let history_path = options
+        .history_file
+        .clone()
+        .unwrap_or_else(default_history_path);
+    // so `cd` doesn't change which file we save to
+    let history_path = env::current_dir()
+        .map(|dir| dir.join(&history_path))
+        .unwrap_or(history_path);
+
+    match history::load(&history_path) {
+        Ok(loaded) => {
+            shell.history = loaded.entries;
+            if let Some(e) = loaded.lock_error {
                 eprintln!(
-                    "Could not read history. Saving history is disabled for this session.\n{}",
+                    "Could not lock history. Saving history is disabled for this session.\n{}",
                     e
                 );
-                Vec::new()
+                command_loop(shell, None);
+                return;
             }
         }
-    };
-
-    command_loop(&mut history);
-
-    if should_not_save_history {
-        return;
+        // there is no history yet, so there's nothing to lose by saving
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => {
+            eprintln!(
+                "Could not read history. Saving history is disabled for this session.\n{}",
+                e
+            );
+            command_loop(shell, None);
+            return;
+        }
     }
 
-    history_path.pop();
-    history_path.push(TEMP_HISTORY_NAME);
-
-    let result = File::create(&history_path).and_then(|f| {
-        let mut writer = BufWriter::new(f);
+    command_loop(shell, Some(&history_path));
+}
 
-        for line in history.iter() {
-            writer.write(line.as_bytes())?;
-        }
+// shown before each line after the first of a command spanning several lines
+const CONTINUATION_PROMPT: &str = "> ";
 
-        writer.flush()
-    });
+enum Read {
+    Command(String),
+    // with Ctrl-C, part way through a command spanning several lines
+    Cancelled,
+    // the input has ended, or can't be read any more
+    End,
+}
 
-    match result {
-        Ok(()) => {
-            let mut target_path = history_path.clone();
-            target_path.pop();
-            target_path.push(HISTORY_NAME);
+// Reads lines until they make up a whole command, or at least until they can
+// not be made into one by adding more, like with an unclosed quote.
+fn read_command(shell: &Shell, prompt: &str) -> Read {
+    let mut input = String::new();
 
-            if let Err(e) = fs::rename(history_path, target_path) {
-                eprintln!("Could not rename history file.\n{}", e);
+    loop {
+        let prompt = if input.is_empty() {
+            prompt
+        } else {
+            CONTINUATION_PROMPT
+        };
+
+        match editor::read_line(prompt, &shell.history, shell.fuzzy_search) {
+            // the editor gives an empty line, without even a newline, for Ctrl-C
+            Ok(Some(line)) if line.is_empty() && !input.is_empty() => return Read::Cancelled,
+            Ok(Some(line)) => input.push_str(&line),
+            // end of file, (Ctrl-D for example,) ends the session the same way `exit` does
+            Ok(None) if input.is_empty() => {
+                println!();
+                return Read::End;
+            }
+            // what we have is still run, so the error about it being unfinished is shown
+            Ok(None) => {
+                println!();
+                return Read::Command(input);
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                eprintln!("Could not read input, it was not valid UTF-8.");
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Could not read input.\n{}", e);
+                return Read::End;
             }
         }
-        Err(e) => {
-            eprintln!("Could not save history.\n{}", e);
-        }
-    }
-}
-
-fn push_history(history: &mut Vec<String>, mut line: String) {
-    //remove all but the last newline
-    let mut index = None;
-    let mut last_char = 'a';
-    for (i, c) in line.chars().enumerate() {
-        if (last_char != '\r' || last_char == '\n') && (c == '\r' || c == '\n') {
-            index = Some(i);
-        }
-        last_char = c;
-    }
 
-    if last_char == '\r' || last_char == '\n' {
-        if let Some(index) = index {
-            line.truncate(index + 1);
-            //in case it's a '\r'
-            line.pop();
-            line.push('\n');
+        match parser::parse(&input) {
+            Err(e) if e.incomplete => {}
+            _ => return Read::Command(input),
         }
     }
-
-    if line.len() > 0 {
-        history.push(line);
-    }
 }
 
-fn command_loop(history: &mut Vec<String>) {
+/// Reads and runs commands until the session ends. Each command is added to
+/// the history file at `history_path` when it finishes, if there is one.
+fn command_loop(shell: &mut Shell, mut history_path: Option<&Path>) {
     loop {
         let current_dir = env::current_dir().unwrap_or_default();
 
-        print!("{}>", current_dir.display());
-        // need to explicitly flush this to ensure it prints before read_line
-        stdout().flush().unwrap();
-
-        let mut input = String::new();
-        stdin().read_line(&mut input).unwrap();
-
-        push_history(history, input.clone());
+        let prompt = if shell.last_status.success() {
+            format!("{}>", current_dir.display())
+        } else {
+            format!("{} [{}]>", current_dir.display(), shell.last_status.code())
+        };
+
+        let input = match read_command(shell, &prompt) {
+            Read::Command(input) => input,
+            Read::Cancelled => continue,
+            Read::End => return,
+        };
+
+        let command = input.trim_end_matches(&['\r', '\n'][..]);
+        let is_recorded = !command.trim().is_empty();
+        if is_recorded {
+            // pushed before running, so `history` can see the command itself
+            shell.history.push(history::Entry::new(command.to_string()));
+        }
+        let started = Instant::now();
 
-        // read_line leaves a trailing newline, which trim removes
-        // this needs to be peekable so we can determine when we are on the last command
-        let mut commands = input.trim().split(" | ").peekable();
-        let mut previous_command = None;
+        let list = match parser::parse(&input) {
+            Ok(list) => Some(list),
+            Err(e) => {
+                eprintln!("{}", e.with_line(&input));
+                shell.last_status = Status::Exited(2);
+                None
+            }
+        };
 
-        while let Some(command) = commands.next() {
-            // everything after the first whitespace character is interpreted as args to the command
-            let mut parts = command.trim().split_whitespace();
-            let command = if let Some(command) = parts.next() {
-                command
-            } else {
-                previous_command = None;
-                continue;
-            };
-            let args = parts;
-
-            match command {
-                "cd" => {
-                    // default to '/' as new directory if one was not provided
-                    let new_dir = args.peekable().peek().map_or("/", |x| *x);
-                    let root = Path::new(new_dir);
-                    if let Err(e) = env::set_current_dir(&root) {
-                        eprintln!("{}", e);
-                    }
+        if let Some(ref list) = list {
+            exec::run_list(shell, list);
+        }
 
-                    previous_command = None;
-                }
-                "echo" => {
-                    let mut sep = "";
-                    for arg in args {
-                        print!("{}{}", sep, arg);
-                        sep = " ";
+        if is_recorded {
+            if let Some(entry) = shell.history.last_mut() {
+                entry.duration = Some(started.elapsed());
+                entry.status = Some(shell.last_status);
+
+                if let Some(path) = history_path {
+                    if let Err(e) = history::append(path, entry) {
+                        eprintln!(
+                            "Could not save history. Saving history is disabled for this session.\n{}",
+                            e
+                        );
+                        history_path = None;
                     }
-                    print!("\n");
-
-                    // need to explicitly flush this to ensure it prints before the next iteration
-                    stdout().flush().unwrap();
-                }
-                "exit" => return,
-                command => {
-                    let stdin = previous_command.map_or(Stdio::inherit(), |output: Child| {
-                        Stdio::from(output.stdout.unwrap())
-                    });
-
-                    let stdout = if commands.peek().is_some() {
-                        // there is another command piped behind this one
-                        // prepare to send output to the next command
-                        Stdio::piped()
-                    } else {
-                        // there are no more commands piped behind this one
-                        // send output to shell stdout
-                        Stdio::inherit()
-                    };
-
-                    let output = Command::new(command)
-                        .args(args)
-                        .stdin(stdin)
-                        .stdout(stdout)
-                        .spawn();
-
-                    match output {
-                        Ok(output) => {
-                            previous_command = Some(output);
-                        }
-                        Err(e) => {
-                            previous_command = None;
-                            eprintln!("{}", e);
-                        }
-                    };
                 }
             }
         }
 
-        if let Some(mut final_command) = previous_command {
-            // block until the final command has finished
-            final_command.wait().unwrap();
+        let list = match list {
+            Some(list) => list,
+            None => continue,
+        };
+
+        if shell.exiting {
+            return;
+        }
+
+        if shell.last_status.is_notable() && !list.items.is_empty() {
+            eprintln!("er: {}", shell.last_status);
         }
     }
 }
@@ -0,0 +1,538 @@
+use crate::expand::is_name;
+use crate::lexer::{
+    self, LexError, LexErrorKind, Quoting, RedirectOp, Token, TokenKind, Word, WordPart,
+};
+use std::fmt;
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+/// How a pipeline is joined to the one before it, which decides whether it runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connector {
+    Always,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommandList {
+    pub items: Vec<(Connector, Pipeline)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<SimpleCommand>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub argv: Vec<Word>,
+    // in the order they should be applied
+    pub redirections: Vec<Redirection>,
+}
+
+/// A leading `NAME=value` word.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Redirection {
+    pub fd: u32,
+    pub target: RedirectTarget,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RedirectTarget {
+    File { path: Word, mode: FileMode },
+    // another file descriptor, as in `2>&1`
+    Fd(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileMode {
+    Read,
+    Write,
+    Append,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: String,
+    // in chars, from the start of the input
+    pub column: usize,
+    // true if the input could become valid by adding more to the end of it,
+    // like a closing quote
+    pub incomplete: bool,
+}
+
+impl SyntaxError {
+    /// Displays the error along with the line it came from and a caret pointing
+    /// at the problem.
+    pub fn with_line<'a>(&'a self, line: &'a str) -> impl fmt::Display + 'a {
+        WithLine { error: self, line }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "syntax error at column {}: {}",
+            self.column + 1,
+            self.message
+        )
+    }
+}
+
+struct WithLine<'a> {
+    error: &'a SyntaxError,
+    line: &'a str,
+}
+
+impl<'a> fmt::Display for WithLine<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.error)?;
+
+        // the input can span several lines, so find the one the column is on
+        let mut column = self.error.column;
+        let mut lines = self.line.split('\n').peekable();
+        while let Some(line) = lines.next() {
+            let length = line.chars().count();
+            if column <= length || lines.peek().is_none() {
+                writeln!(f, "{}", line.trim_end_matches('\r'))?;
+                break;
+            }
+            column -= length + 1;
+        }
+
+        write!(f, "{:>width$}", "^", width = column + 1)
+    }
+}
+
+impl From<LexError> for SyntaxError {
+    fn from(e: LexError) -> Self {
+        SyntaxError {
+            message: e.kind.to_string(),
+            column: e.column,
+            incomplete: e.kind != LexErrorKind::TrailingBackslash,
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<CommandList, SyntaxError> {
+    let tokens = lexer::tokenize(input)?;
+
+    let mut parser = Parser {
+        tokens: tokens.into_iter().peekable(),
+        end_column: input.trim_end_matches(&['\r', '\n'][..]).chars().count(),
+    };
+
+    parser.command_list()
+}
+
+struct Parser {
+    tokens: Peekable<IntoIter<Token>>,
+    end_column: usize,
+}
+
+impl Parser {
+    fn next_column(&mut self) -> usize {
+        let end_column = self.end_column;
+        self.tokens.peek().map_or(end_column, |t| t.column)
+    }
+
+    fn error(&mut self, message: &str) -> SyntaxError {
+        let column = self.next_column();
+        let message = match self.tokens.peek() {
+            Some(token) => format!("{} before {}", message, describe(&token.kind)),
+            None => format!("{} at end of input", message),
+        };
+
+        SyntaxError {
+            message,
+            column,
+            incomplete: false,
+        }
+    }
+
+    // for errors where the rest of the command could be on the next line
+    fn missing_command(&mut self) -> SyntaxError {
+        let mut error = self.error("expected a command");
+        error.incomplete = self.tokens.peek().is_none();
+        error
+    }
+
+    fn command_list(&mut self) -> Result<CommandList, SyntaxError> {
+        let mut list = CommandList::default();
+        let mut connector = Connector::Always;
+
+        while self.tokens.peek().is_some() {
+            let pipeline = self.pipeline()?;
+            list.items.push((connector, pipeline));
+
+            connector = match self.tokens.next().map(|t| t.kind) {
+                None | Some(TokenKind::Semicolon) => Connector::Always,
+                Some(TokenKind::And) => Connector::And,
+                Some(TokenKind::Or) => Connector::Or,
+                // `pipeline` only stops at the tokens above
+                Some(TokenKind::Word(_))
+                | Some(TokenKind::Pipe)
+                | Some(TokenKind::PipeAnd)
+                | Some(TokenKind::Redirect { .. }) => unreachable!(),
+            };
+
+            if connector != Connector::Always && self.tokens.peek().is_none() {
+                return Err(self.missing_command());
+            }
+        }
+
+        Ok(list)
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, SyntaxError> {
+        let mut commands = vec![self.simple_command()?];
+
+        loop {
+            let pipe_stderr = match self.tokens.peek().map(|t| &t.kind) {
+                Some(TokenKind::Pipe) => false,
+                Some(TokenKind::PipeAnd) => true,
+                _ => break,
+            };
+            self.tokens.next();
+
+            if pipe_stderr {
+                if let Some(command) = commands.last_mut() {
+                    // `|&` is short for `2>&1 |`
+                    command.redirections.push(Redirection {
+                        fd: 2,
+                        target: RedirectTarget::Fd(1),
+                    });
+                }
+            }
+            commands.push(self.simple_command()?);
+        }
+
+        Ok(Pipeline { commands })
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, SyntaxError> {
+        let mut command = SimpleCommand::default();
+
+        loop {
+            let word = match self.tokens.peek().map(|t| &t.kind) {
+                Some(TokenKind::Word(_)) => match self.tokens.next().map(|t| t.kind) {
+                    Some(TokenKind::Word(word)) => word,
+                    _ => unreachable!(),
+                },
+                Some(&TokenKind::Redirect { fd, op }) => {
+                    self.tokens.next();
+                    self.redirection(&mut command, fd, op)?;
+                    continue;
+                }
+                _ => break,
+            };
+
+            if command.argv.is_empty() {
+                if let Some(assignment) = split_assignment(&word) {
+                    command.assignments.push(assignment);
+                    continue;
+                }
+            }
+
+            command.argv.push(word);
+        }
+
+        if command.argv.is_empty()
+            && command.assignments.is_empty()
+            && command.redirections.is_empty()
+        {
+            return Err(self.missing_command());
+        }
+
+        Ok(command)
+    }
+
+    fn redirection(
+        &mut self,
+        command: &mut SimpleCommand,
+        fd: Option<u32>,
+        op: RedirectOp,
+    ) -> Result<(), SyntaxError> {
+        let operator = describe(&TokenKind::Redirect { fd, op });
+        let target = match self.tokens.peek() {
+            Some(Token {
+                kind: TokenKind::Word(word),
+                ..
+            }) => word.clone(),
+            _ => return Err(self.error(&format!("expected a file name after {}", operator))),
+        };
+
+        if op == RedirectOp::Duplicate {
+            let target_fd = match target.to_string().parse() {
+                Ok(target_fd) => target_fd,
+                Err(_) => {
+                    return Err(
+                        self.error(&format!("expected a file descriptor after {}", operator))
+                    )
+                }
+            };
+            self.tokens.next();
+
+            command.redirections.push(Redirection {
+                fd: fd.unwrap_or(1),
+                target: RedirectTarget::Fd(target_fd),
+            });
+            return Ok(());
+        }
+        self.tokens.next();
+
+        let (fd, mode) = match op {
+            RedirectOp::Read => (fd.unwrap_or(0), FileMode::Read),
+            RedirectOp::Write | RedirectOp::WriteAll => (fd.unwrap_or(1), FileMode::Write),
+            RedirectOp::Append | RedirectOp::AppendAll => (fd.unwrap_or(1), FileMode::Append),
+            RedirectOp::Duplicate => unreachable!(),
+        };
+
+        command.redirections.push(Redirection {
+            fd,
+            target: RedirectTarget::File { path: target, mode },
+        });
+
+        if op == RedirectOp::WriteAll || op == RedirectOp::AppendAll {
+            // `&> file` is short for `> file 2>&1`
+            command.redirections.push(Redirection {
+                fd: 2,
+                target: RedirectTarget::Fd(1),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn describe(kind: &TokenKind) -> String {
+    match kind {
+        TokenKind::Word(word) => format!("`{}`", word),
+        TokenKind::And => "`&&`".to_string(),
+        TokenKind::Or => "`||`".to_string(),
+        TokenKind::Semicolon => "`;`".to_string(),
+        TokenKind::Pipe => "`|`".to_string(),
+        TokenKind::PipeAnd => "`|&`".to_string(),
+        TokenKind::Redirect { fd: Some(fd), op } => format!("`{}{}`", fd, op),
+        TokenKind::Redirect { fd: None, op } => format!("`{}`", op),
+    }
+}
+
+fn split_assignment(word: &Word) -> Option<Assignment> {
+    let first = word.parts.first()?;
+    if first.quoting != Quoting::Bare {
+        return None;
+    }
+
+    let equals = first.text.find('=')?;
+    let name = &first.text[..equals];
+    if !is_name(name) {
+        return None;
+    }
+
+    let mut value = Word::default();
+    let rest = &first.text[equals + 1..];
+    if !rest.is_empty() {
+        value.parts.push(WordPart {
+            text: rest.to_string(),
+            quoting: Quoting::Bare,
+        });
+    }
+    value.parts.extend(word.parts[1..].iter().cloned());
+
+    Some(Assignment {
+        name: name.to_string(),
+        value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expand::expand;
+    use crate::shell::Shell;
+
+    fn word(text: &str) -> Word {
+        Word {
+            parts: vec![WordPart {
+                text: text.to_string(),
+                quoting: Quoting::Bare,
+            }],
+        }
+    }
+
+    fn only_command(input: &str) -> SimpleCommand {
+        let mut list = parse(input).unwrap();
+        assert_eq!(list.items.len(), 1);
+        let (_, mut pipeline) = list.items.remove(0);
+        assert_eq!(pipeline.commands.len(), 1);
+        pipeline.commands.remove(0)
+    }
+
+    fn file(fd: u32, path: &str, mode: FileMode) -> Redirection {
+        Redirection {
+            fd,
+            target: RedirectTarget::File {
+                path: word(path),
+                mode,
+            },
+        }
+    }
+
+    fn duplicate(fd: u32, target: u32) -> Redirection {
+        Redirection {
+            fd,
+            target: RedirectTarget::Fd(target),
+        }
+    }
+
+    #[test]
+    fn connectors() {
+        let list = parse("a && b || c; d").unwrap();
+        let connectors: Vec<Connector> = list.items.iter().map(|(c, _)| *c).collect();
+        assert_eq!(
+            connectors,
+            vec![
+                Connector::Always,
+                Connector::And,
+                Connector::Or,
+                Connector::Always
+            ]
+        );
+
+        // a trailing `;` ends the list without adding to it
+        assert_eq!(parse("a;").unwrap().items.len(), 1);
+        assert_eq!(parse("").unwrap(), CommandList::default());
+    }
+
+    #[test]
+    fn pipes_without_spaces() {
+        let list = parse("a|b|&c").unwrap();
+        let commands = &list.items[0].1.commands;
+
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[0].argv, vec![word("a")]);
+        assert!(commands[0].redirections.is_empty());
+        // `|&` sends the stderr of the command before it down the pipe too
+        assert_eq!(commands[1].redirections, vec![duplicate(2, 1)]);
+        assert_eq!(commands[2].argv, vec![word("c")]);
+    }
+
+    #[test]
+    fn quoting_is_kept_for_expansion() {
+        let command = only_command("echo '$a' \"$b\"");
+        assert_eq!(command.argv[1].parts[0].quoting, Quoting::Literal);
+        assert_eq!(command.argv[2].parts[0].quoting, Quoting::Double);
+    }
+
+    #[test]
+    fn quotes_in_defaults_are_removed_when_expanded() {
+        let shell = Shell::new();
+        let command = only_command(
+            r#"echo ${ER_TEST_UNSET:-"q r"} ${ER_TEST_UNSET:-'vim'} "${ER_TEST_UNSET:-"a  b"}" ${ER_TEST_UNSET:-a\ b}"#,
+        );
+        // the whole of each `${...}` is one word, quotes and all
+        assert_eq!(command.argv.len(), 5);
+
+        let expanded: Vec<String> = command.argv[1..]
+            .iter()
+            .map(|word| expand(word, &shell).unwrap())
+            .collect();
+        assert_eq!(expanded, vec!["q r", "vim", "a  b", "a b"]);
+    }
+
+    #[test]
+    fn redirection_fds_default_by_direction() {
+        let command = only_command("a <in >out >>log 2>err 3<x >&2");
+        assert_eq!(command.argv, vec![word("a")]);
+        assert_eq!(
+            command.redirections,
+            vec![
+                file(0, "in", FileMode::Read),
+                file(1, "out", FileMode::Write),
+                file(1, "log", FileMode::Append),
+                file(2, "err", FileMode::Write),
+                file(3, "x", FileMode::Read),
+                duplicate(1, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn write_all_is_two_redirections() {
+        assert_eq!(
+            only_command("a &> out").redirections,
+            vec![file(1, "out", FileMode::Write), duplicate(2, 1)]
+        );
+        assert_eq!(
+            only_command("a &>> out").redirections,
+            vec![file(1, "out", FileMode::Append), duplicate(2, 1)]
+        );
+    }
+
+    #[test]
+    fn assignments_only_come_first() {
+        let command = only_command("A=1 B='x y' cmd C=2");
+        let names: Vec<&str> = command
+            .assignments
+            .iter()
+            .map(|a| a.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["A", "B"]);
+        assert_eq!(command.assignments[1].value.to_string(), "x y");
+        assert_eq!(command.argv, vec![word("cmd"), word("C=2")]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = parse("a | | b").unwrap_err();
+        assert_eq!(error.column, 4);
+        assert!(!error.incomplete);
+
+        let error = parse("a > ;").unwrap_err();
+        assert_eq!(error.message, "expected a file name after `>` before `;`");
+        let error = parse("a 2>").unwrap_err();
+        assert_eq!(
+            error.message,
+            "expected a file name after `2>` at end of input"
+        );
+        let error = parse("a 2>&x").unwrap_err();
+        assert_eq!(
+            error.message,
+            "expected a file descriptor after `2>&` before `x`"
+        );
+
+        // more input could finish these
+        assert!(parse("a &&").unwrap_err().incomplete);
+        assert!(parse("a |").unwrap_err().incomplete);
+        assert!(parse("echo 'a").unwrap_err().incomplete);
+        assert!(parse("echo a \\\n").unwrap_err().incomplete);
+        assert!(!parse("echo a \\").unwrap_err().incomplete);
+    }
+
+    #[test]
+    fn caret_points_into_the_right_line() {
+        let input = "echo 'a\nb' &&\n  || c\n";
+        let error = parse(input).unwrap_err();
+        assert_eq!(error.column, 16);
+        assert_eq!(
+            error.with_line(input).to_string(),
+            "syntax error at column 17: expected a command before `||`\n  || c\n  ^"
+        );
+
+        let error = parse("a &&\n;").unwrap_err();
+        assert_eq!(
+            error.with_line("a &&\n;").to_string().lines().last(),
+            Some("^")
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The directories in `PATH`, in the order they are searched.
+pub fn directories() -> Vec<PathBuf> {
+    match env::var_os("PATH") {
+        Some(paths) => env::split_paths(&paths)
+            .map(|dir| {
+                // an empty entry means the current directory
+                if dir.as_os_str().is_empty() {
+                    PathBuf::from(".")
+                } else {
+                    dir
+                }
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// The executables in the directories on `PATH`. Reading directories with
+/// thousands of entries is slow, so each one is only read again when `PATH`
+/// changes or its modification time does, which happens whenever something in
+/// it is added, removed or renamed.
+#[derive(Default)]
+pub struct Index {
+    // the value of `PATH` the directories came from
+    path: Option<OsString>,
+    directories: Vec<IndexedDirectory>,
+}
+
+struct IndexedDirectory {
+    path: PathBuf,
+    is_read: bool,
+    modified: Option<SystemTime>,
+    names: HashSet<String>,
+}
+
+impl Index {
+    /// Reads every directory again, even ones that don't seem to have changed.
+    pub fn rebuild(&mut self) {
+        *self = Index::default();
+        self.refresh();
+    }
+
+    /// Returns every executable file that running `name` could mean, in the
+    /// order the directories in `PATH` are searched, so the first one is what
+    /// would run. Like other shells, a name containing a `/` is a path rather
+    /// than something to search for.
+    pub fn find_all(&mut self, name: &str) -> Vec<PathBuf> {
+        if name.is_empty() {
+            return Vec::new();
+        }
+
+        if name.contains('/') {
+            let path = PathBuf::from(name);
+            return if is_executable(&path) {
+                vec![path]
+            } else {
+                Vec::new()
+            };
+        }
+
+        self.refresh();
+        self.directories
+            .iter()
+            .filter(|directory| directory.names.contains(name))
+            .map(|directory| directory.path.join(name))
+            // a file's permissions can change without its directory changing
+            .filter(|path| is_executable(path))
+            .collect()
+    }
+
+    /// The names of every executable on `PATH`, in no particular order and
+    /// possibly with duplicates.
+    pub fn names(&mut self) -> impl Iterator<Item = &str> {
+        self.refresh();
+        self.directories
+            .iter()
+            .flat_map(|directory| directory.names.iter().map(String::as_str))
+    }
+
+    fn refresh(&mut self) {
+        let path = env::var_os("PATH");
+        if path != self.path {
+            // directories that are still on `PATH` may not need to be read again
+            let mut previous: HashMap<PathBuf, IndexedDirectory> = self
+                .directories
+                .drain(..)
+                .map(|directory| (directory.path.clone(), directory))
+                .collect();
+
+            self.directories = directories()
+                .into_iter()
+                .map(|dir| {
+                    previous.remove(&dir).unwrap_or(IndexedDirectory {
+                        path: dir,
+                        is_read: false,
+                        modified: None,
+                        names: HashSet::new(),
+                    })
+                })
+                .collect();
+            self.path = path;
+        }
+
+        for directory in self.directories.iter_mut() {
+            directory.refresh();
+        }
+    }
+}
+
+impl IndexedDirectory {
+    fn refresh(&mut self) {
+        // checked before reading, so a change made while we read is noticed next time
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if self.is_read && modified == self.modified {
+            return;
+        }
+
+        self.names.clear();
+        if let Ok(entries) = fs::read_dir(&self.path) {
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                // names that aren't valid UTF-8 couldn't have been typed as a command
+                if let Ok(name) = entry.file_name().into_string() {
+                    if is_executable(&entry.path()) {
+                        self.names.insert(name);
+                    }
+                }
+            }
+        }
+
+        self.is_read = true;
+        self.modified = modified;
+    }
+}
+
+/// Whether `path` is a file, (after following symlinks,) with any of its
+/// executable permission bits set.
+pub fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
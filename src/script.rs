@@ -0,0 +1,45 @@
+use crate::exec;
+use crate::parser;
+use crate::shell::Shell;
+use crate::status::Status;
+
+/// Runs `source` a line at a time, as if each line had been typed at the prompt,
+/// except that a syntax error stops the script. `name` is used to say where
+/// errors came from.
+pub fn run(shell: &mut Shell, name: &str, source: &str) {
+    // lines that so far only make up part of a command, like an unclosed quote
+    let mut pending = String::new();
+    let mut first_line_number = 1;
+
+    let mut lines = source.lines().enumerate().peekable();
+    while let Some((index, line)) = lines.next() {
+        if pending.is_empty() {
+            first_line_number = index + 1;
+        }
+        pending.push_str(line);
+        pending.push('\n');
+
+        let list = match parser::parse(&pending) {
+            Ok(list) => list,
+            Err(e) if e.incomplete && lines.peek().is_some() => continue,
+            Err(e) => {
+                let line_number = first_line_number
+                    + pending
+                        .chars()
+                        .take(e.column)
+                        .filter(|&c| c == '\n')
+                        .count();
+
+                eprintln!("{}: line {}: {}", name, line_number, e.with_line(&pending));
+                shell.last_status = Status::Exited(2);
+                return;
+            }
+        };
+        pending.clear();
+
+        exec::run_list(shell, &list);
+        if shell.exiting {
+            return;
+        }
+    }
+}
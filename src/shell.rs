@@ -0,0 +1,39 @@
+use crate::history;
+use crate::path;
+use crate::status::Status;
+use std::path::PathBuf;
+
+/// The state of an `er` session that commands can read or change.
+pub struct Shell {
+    pub last_status: Status,
+    // set by the `exit` builtin
+    pub exiting: bool,
+    // when set, a pipeline fails if any of its commands do, not just the last one
+    pub pipefail: bool,
+    // when set, the session ends after the first command that fails, other than
+    // those on the left of a `&&` or `||`
+    pub errexit: bool,
+    // when set, Ctrl-R matches lines with the searched for characters in
+    // order, rather than only ones containing them all together
+    pub fuzzy_search: bool,
+    // the directories `pushd` saved, most recent first
+    pub dir_stack: Vec<PathBuf>,
+    // the commands entered interactively, oldest first
+    pub history: Vec<history::Entry>,
+    pub path_index: path::Index,
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        Shell {
+            last_status: Status::SUCCESS,
+            exiting: false,
+            pipefail: false,
+            errexit: false,
+            fuzzy_search: false,
+            dir_stack: Vec::new(),
+            history: Vec::new(),
+            path_index: path::Index::default(),
+        }
+    }
+}
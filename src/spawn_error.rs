@@ -0,0 +1,290 @@
+use crate::builtins;
+use crate::path;
+use crate::status::Status;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Linux's "Exec format error", for files that aren't a program or a script with a `#!`
+const ENOEXEC: i32 = 8;
+
+/// Why a command could not be started, worked out from the error spawning it
+/// returned along with a look at the file it was trying to run.
+#[derive(Debug)]
+pub enum SpawnError {
+    // with the suggestions to show for it
+    NotFound(Vec<String>),
+    NoSuchFile,
+    IsADirectory(PathBuf),
+    NotExecutable(PathBuf),
+    // executable, but neither a program nor a script starting with `#!`
+    UnknownFormat(PathBuf),
+    PermissionDenied(PathBuf),
+    Other(io::Error),
+}
+
+impl SpawnError {
+    pub fn new(command: &str, e: io::Error, path_index: &mut path::Index) -> Self {
+        // for a name without a `/`, which file on `PATH` it was that couldn't be run
+        let path = if command.contains('/') {
+            Some(PathBuf::from(command))
+        } else {
+            find_any_file(command)
+        };
+        let metadata = path.as_ref().and_then(|path| fs::metadata(path).ok());
+
+        match (path, metadata) {
+            (None, _) | (Some(_), None) if e.kind() == io::ErrorKind::NotFound => {
+                if command.contains('/') {
+                    SpawnError::NoSuchFile
+                } else {
+                    SpawnError::NotFound(suggestions(command, path_index))
+                }
+            }
+            (Some(path), Some(metadata)) if metadata.is_dir() => SpawnError::IsADirectory(path),
+            (Some(path), Some(_)) if !path::is_executable(&path) => SpawnError::NotExecutable(path),
+            (Some(path), Some(_)) if e.raw_os_error() == Some(ENOEXEC) => {
+                SpawnError::UnknownFormat(path)
+            }
+            (Some(path), Some(_)) if e.kind() == io::ErrorKind::PermissionDenied => {
+                SpawnError::PermissionDenied(path)
+            }
+            _ => SpawnError::Other(e),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            SpawnError::NotFound(_) | SpawnError::NoSuchFile => Status::NOT_FOUND,
+            SpawnError::IsADirectory(_)
+            | SpawnError::NotExecutable(_)
+            | SpawnError::UnknownFormat(_)
+            | SpawnError::PermissionDenied(_) => Status::NOT_EXECUTABLE,
+            SpawnError::Other(e) => Status::from_spawn_error(e),
+        }
+    }
+
+    /// Displays the error as coming from trying to run `command`.
+    pub fn with_command<'a>(&'a self, command: &'a str) -> impl fmt::Display + 'a {
+        WithCommand {
+            error: self,
+            command,
+        }
+    }
+}
+
+struct WithCommand<'a> {
+    error: &'a SpawnError,
+    command: &'a str,
+}
+
+impl<'a> fmt::Display for WithCommand<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.command)?;
+
+        // the path is only worth showing when it isn't what was typed
+        let found = |path: &PathBuf| {
+            if path.as_os_str() == self.command {
+                String::new()
+            } else {
+                format!(" ({})", path.display())
+            }
+        };
+
+        match self.error {
+            SpawnError::NotFound(suggestions) => {
+                f.write_str("command not found")?;
+                if let Some((last, rest)) = suggestions.split_last() {
+                    f.write_str("\ndid you mean ")?;
+                    for (i, suggestion) in rest.iter().enumerate() {
+                        if i > 0 {
+                            f.write_str(", ")?;
+                        }
+                        write!(f, "`{}`", suggestion)?;
+                    }
+                    if !rest.is_empty() {
+                        f.write_str(" or ")?;
+                    }
+                    write!(f, "`{}`?", last)?;
+                }
+                Ok(())
+            }
+            SpawnError::NoSuchFile => f.write_str("no such file or directory"),
+            SpawnError::IsADirectory(path) => write!(f, "is a directory{}", found(path)),
+            SpawnError::NotExecutable(path) => {
+                write!(f, "not executable{}", found(path))
+            }
+            SpawnError::UnknownFormat(path) => write!(
+                f,
+                "not a program, or a script starting with `#!`{}",
+                found(path)
+            ),
+            SpawnError::PermissionDenied(path) => {
+                write!(f, "permission denied{}", found(path))
+            }
+            SpawnError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// Unlike `path::find_all`, this also finds files that aren't executable, since
+// those are what cause the errors we want to explain.
+fn find_any_file(name: &str) -> Option<PathBuf> {
+    path::directories()
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.exists())
+}
+
+const MAX_SUGGESTIONS: usize = 3;
+
+// The builtins and programs on `PATH` with names closest to `name`, as long as
+// they are close enough that `name` could be a typo of them.
+fn suggestions(name: &str, path_index: &mut path::Index) -> Vec<String> {
+    let mut names: Vec<&str> = builtins::names().collect();
+    names.extend(path_index.names());
+
+    closest(name, names)
+}
+
+// The closest first, with ties in alphabetical order.
+fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    // Longer names have room for more typos. One edit could turn a name of one
+    // or two characters into almost anything, so those only get suggestions
+    // for swapped characters.
+    let max_distance = name.chars().count() / 3;
+
+    let mut candidates: Vec<(usize, String)> = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let distance = distance(name, candidate);
+            if distance <= max_distance || is_swap(name, candidate) {
+                Some((distance, candidate.to_string()))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    candidates.sort();
+    candidates.dedup_by(|a, b| a.1 == b.1);
+
+    candidates
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .take(MAX_SUGGESTIONS)
+        .collect()
+}
+
+// Whether `b` is `a` with two neighbouring characters swapped.
+fn is_swap(a: &str, b: &str) -> bool {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let different: Vec<usize> = (0..a.len()).filter(|&i| a[i] != b[i]).collect();
+    match different[..] {
+        [i, j] => j == i + 1 && a[i] == b[j] && a[j] == b[i],
+        _ => false,
+    }
+}
+
+// The number of single character insertions, deletions, substitutions, or swaps
+// of neighbouring characters needed to turn `a` into `b`. Swaps count as one
+// edit since `sl` for `ls` is as common a typo as any.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // `rows[i][j]` is the distance between the first `i` chars of `a` and the
+    // first `j` chars of `b`
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut best = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + substitution);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+
+            rows[i][j] = best;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: &[&str] = &["[", "bq", "cargo", "cat", "cd", "git", "gitk", "ls", "w"];
+
+    #[test]
+    fn distances() {
+        assert_eq!(distance("", ""), 0);
+        assert_eq!(distance("cargo", "cargo"), 0);
+        assert_eq!(distance("", "ls"), 2);
+        assert_eq!(distance("cago", "cargo"), 1);
+        assert_eq!(distance("carrgo", "cargo"), 1);
+        assert_eq!(distance("cergo", "cargo"), 1);
+        // a swap is one edit, not two
+        assert_eq!(distance("sl", "ls"), 1);
+        assert_eq!(distance("gti", "git"), 1);
+        assert_eq!(distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn swaps() {
+        assert!(is_swap("sl", "ls"));
+        assert!(is_swap("gti", "git"));
+        assert!(is_swap("cagro", "cargo"));
+        assert!(!is_swap("ls", "ls"));
+        assert!(!is_swap("abc", "cba"));
+        assert!(!is_swap("ab", "abc"));
+        assert!(!is_swap("abcd", "badc"));
+    }
+
+    #[test]
+    fn typos_get_suggestions() {
+        assert_eq!(closest("gti", NAMES.iter().copied()), vec!["git"]);
+        assert_eq!(closest("cagro", NAMES.iter().copied()), vec!["cargo"]);
+        assert_eq!(closest("carg", NAMES.iter().copied()), vec!["cargo"]);
+    }
+
+    #[test]
+    fn short_names_only_get_swaps() {
+        assert_eq!(closest("sl", NAMES.iter().copied()), vec!["ls"]);
+        // one edit away from `cd`, but not a swap of it
+        assert_eq!(closest("cs", NAMES.iter().copied()), Vec::<String>::new());
+        assert_eq!(closest("b", NAMES.iter().copied()), Vec::<String>::new());
+        assert_eq!(closest("xq", NAMES.iter().copied()), Vec::<String>::new());
+        assert_eq!(closest("dc", NAMES.iter().copied()), vec!["cd"]);
+    }
+
+    #[test]
+    fn at_most_a_few_suggestions_closest_first() {
+        let names = ["abcdez", "abcdef", "abcdxy", "abcdeg", "abcdeh", "zzzzzz"];
+
+        // the three at one edit, alphabetically, before the one at two
+        assert_eq!(
+            closest("abcdex", names.iter().copied()),
+            vec!["abcdef", "abcdeg", "abcdeh"]
+        );
+        assert_eq!(
+            closest("abcdex", ["abcdxy", "abcdez"].iter().copied()),
+            vec!["abcdez", "abcdxy"]
+        );
+    }
+}
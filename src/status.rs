@@ -0,0 +1,131 @@
+use std::fmt;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+/// How a command, builtin or otherwise, finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Exited(i32),
+    Signaled { signal: i32, core_dumped: bool },
+}
+
+impl Status {
+    pub const SUCCESS: Status = Status::Exited(0);
+    pub const FAILURE: Status = Status::Exited(1);
+    // the same codes other shells use
+    pub const NOT_EXECUTABLE: Status = Status::Exited(126);
+    pub const NOT_FOUND: Status = Status::Exited(127);
+
+    /// The status to report when a command could not be started at all.
+    pub fn from_spawn_error(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => Status::NOT_FOUND,
+            io::ErrorKind::PermissionDenied => Status::NOT_EXECUTABLE,
+            _ => Status::FAILURE,
+        }
+    }
+
+    pub fn success(self) -> bool {
+        self == Status::SUCCESS
+    }
+
+    /// The number used for `$?` and for er's own exit code. Like other shells,
+    /// being killed by a signal is reported as 128 plus the signal number.
+    pub fn code(self) -> i32 {
+        match self {
+            Status::Exited(code) => code,
+            Status::Signaled { signal, .. } => 128 + signal,
+        }
+    }
+
+    /// Whether the status is worth telling the user about after a command runs.
+    /// Interrupting or breaking a pipe are usually things the user did on purpose.
+    pub fn is_notable(self) -> bool {
+        match self {
+            Status::Exited(code) => code != 0,
+            Status::Signaled { signal, .. } => signal != SIGINT && signal != SIGPIPE,
+        }
+    }
+}
+
+impl From<ExitStatus> for Status {
+    fn from(status: ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => Status::Exited(code),
+            (None, Some(signal)) => Status::Signaled {
+                signal,
+                core_dumped: status.core_dumped(),
+            },
+            // stopped or continued, which we don't ask to be told about
+            (None, None) => Status::FAILURE,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Status::Exited(code) => write!(f, "exited with status {}", code),
+            Status::Signaled {
+                signal,
+                core_dumped,
+            } => {
+                match signal_name(signal) {
+                    Some(name) => write!(f, "killed by {}", name)?,
+                    None => write!(f, "killed by signal {}", signal)?,
+                }
+                if core_dumped {
+                    f.write_str(" (core dumped)")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+const SIGINT: i32 = 2;
+const SIGPIPE: i32 = 13;
+
+// Linux's numbering
+const SIGNAL_NAMES: [&str; 31] = [
+    "SIGHUP",
+    "SIGINT",
+    "SIGQUIT",
+    "SIGILL",
+    "SIGTRAP",
+    "SIGABRT",
+    "SIGBUS",
+    "SIGFPE",
+    "SIGKILL",
+    "SIGUSR1",
+    "SIGSEGV",
+    "SIGUSR2",
+    "SIGPIPE",
+    "SIGALRM",
+    "SIGTERM",
+    "SIGSTKFLT",
+    "SIGCHLD",
+    "SIGCONT",
+    "SIGSTOP",
+    "SIGTSTP",
+    "SIGTTIN",
+    "SIGTTOU",
+    "SIGURG",
+    "SIGXCPU",
+    "SIGXFSZ",
+    "SIGVTALRM",
+    "SIGPROF",
+    "SIGWINCH",
+    "SIGIO",
+    "SIGPWR",
+    "SIGSYS",
+];
+
+fn signal_name(signal: i32) -> Option<&'static str> {
+    if signal < 1 {
+        return None;
+    }
+
+    SIGNAL_NAMES.get(signal as usize - 1).copied()
+}
@@ -0,0 +1,113 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+
+const ER: &str = env!("CARGO_BIN_EXE_er");
+
+// a fresh directory for each test, so they can run at the same time
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("er-tests-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Starts an interactive instance that reads `input` as if it were typed.
+fn start(history_path: &Path, dir: &Path, input: String) -> Child {
+    let mut child = Command::new(ER)
+        .args(["-q", "--history-file"])
+        .arg(history_path)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child
+}
+
+// Checks the instance finished without complaining, about the history for example.
+fn finish(child: Child) {
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+// Runs `history` in a new instance, which loads the file as it starts up, and
+// returns the commands it lists.
+fn list(history_path: &Path, dir: &Path) -> Vec<String> {
+    finish(start(history_path, dir, "history > listing\n".to_string()));
+
+    fs::read_to_string(dir.join("listing"))
+        .unwrap()
+        .lines()
+        // after the number and the two spaces that follow it
+        .map(|line| line.trim_start().split_once("  ").unwrap().1.to_string())
+        .collect()
+}
+
+#[test]
+fn instances_share_a_history_file() {
+    const WRITERS: usize = 4;
+    const COMMANDS: usize = 500;
+
+    let dir = temp_dir("shared");
+    let path = dir.join("er_history");
+
+    let mut writers: Vec<Child> = (0..WRITERS)
+        .map(|writer| {
+            let input = (0..COMMANDS)
+                .map(|i| format!("echo {} {}\n", writer, i))
+                .collect();
+            start(&path, &dir, input)
+        })
+        .collect();
+
+    // other instances starting up, and so loading the file, while it is added to
+    let mut readers = 0;
+    while !writers.is_empty() {
+        for command in list(&path, &dir) {
+            let is_whole = command == "history > listing"
+                || matches!(
+                    command.split(' ').collect::<Vec<_>>()[..],
+                    ["echo", writer, i] if writer.parse::<usize>().is_ok()
+                        && i.parse::<usize>().is_ok()
+                );
+            assert!(is_whole, "cut short: {:?}", command);
+        }
+        readers += 1;
+
+        let mut running = Vec::new();
+        for mut writer in writers {
+            match writer.try_wait().unwrap() {
+                Some(_) => finish(writer),
+                None => running.push(writer),
+            }
+        }
+        writers = running;
+    }
+
+    let commands = list(&path, &dir);
+    assert_eq!(commands.len(), WRITERS * COMMANDS + readers + 1);
+    // each instance's commands are all there, in the order it ran them
+    for writer in 0..WRITERS {
+        let prefix = format!("echo {} ", writer);
+        let written: Vec<&str> = commands
+            .iter()
+            .filter_map(|command| command.strip_prefix(&prefix))
+            .collect();
+        let expected: Vec<String> = (0..COMMANDS).map(|i| i.to_string()).collect();
+        assert_eq!(written, expected);
+    }
+
+    let _ = fs::remove_dir_all(dir);
+}